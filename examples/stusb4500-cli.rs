@@ -0,0 +1,43 @@
+//! Read the state of a STUSB4500 from Linux over I2C.
+//!
+//! ```text
+//! stusb4500-cli <i2c device> [status|monitor]
+//! ```
+//! `status` prints a one-line summary, `monitor` prints a new line each time
+//! the state changes.
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use linux_embedded_hal::I2cdev;
+use stusb4500::{Address, STUSB4500};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <i2c device> [status|monitor]", args[0]);
+        process::exit(2);
+    }
+    let i2c = I2cdev::new(&args[1]).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    });
+    let mut stusb = STUSB4500::new(i2c, Address::Default);
+
+    let result = match args.get(2).map(String::as_str).unwrap_or("status") {
+        "status" => stusb.snapshot().map(|s| println!("{}", s.summary())),
+        "monitor" => stusb.monitor_changes(Duration::from_millis(200), |s| {
+            println!("{}", s.summary());
+            true
+        }),
+        cmd => {
+            eprintln!("unknown command: {}", cmd);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {:?}", e);
+        process::exit(1);
+    }
+}
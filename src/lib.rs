@@ -1,25 +1,54 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 extern crate byteorder;
 extern crate bitflags;
 extern crate embedded_hal as hal;
+extern crate heapless;
 
 use byteorder::{ByteOrder, LittleEndian};
+use hal::blocking::delay::{DelayMs, DelayUs};
 use hal::blocking::i2c;
+use hal::digital::v2::OutputPin;
+
+/// Emits a `log` trace event with the `log` feature, compiles away otherwise
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
 
+pub mod nvm;
 pub mod pdo;
 pub mod rdo;
 pub mod registers;
+pub mod snapshot;
 
+use nvm::*;
 use pdo::*;
 use rdo::*;
 use registers::*;
+use snapshot::*;
 
 pub const STUSB4500_ADDR: u8 = 0x28;
 
+/// Known values of the `DEVICE_ID` register
+pub const STUSB4500_DEVICE_IDS: [u8; 2] = [0x21, 0x25];
+
+/// Time for the chip to reload the NVM and restart after a software reset, in ms
+const RESET_TIME_MS: u16 = 25;
+
 /// Address enum for STUSB4500
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub enum Address {
     /// Default address with all address pins tied low
+    #[default]
     Default,
     /// Address determined by A1 and A0 pins. True = tied high, low = tied low.
     Strap(bool, bool),
@@ -31,34 +60,129 @@ impl Address {
     pub(crate) fn addr(&self) -> u8 {
         match self {
             Address::Default => STUSB4500_ADDR,
-            Address::Strap(a1, a0) => STUSB4500_ADDR | (*a1 as u8) << 1 | (*a0 as u8) << 0,
+            Address::Strap(a1, a0) => STUSB4500_ADDR | (*a1 as u8) << 1 | (*a0 as u8),
             Address::Custom(addr) => *addr,
         }
     }
-}
 
-impl Default for Address {
-    fn default() -> Self {
-        Address::Default
+    /// All strap combinations as `(a1, a0, address)`, in address order
+    pub fn strap_table() -> [(bool, bool, u8); 4] {
+        let mut table = [(false, false, 0); 4];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let (a1, a0) = (i & 0x02 != 0, i & 0x01 != 0);
+            *entry = (a1, a0, Address::Strap(a1, a0).addr());
+        }
+        table
     }
 }
 
 #[derive(Debug)]
 pub enum Error<I2C> {
     I2CError(I2C),
+    /// A PDO read from the chip could not be decoded
     InvalidPdo,
-    OutaRangePdo
+    /// Only fixed PDOs can be advertised by the sink
+    NotFixedPdo,
+    OutaRangePdo,
+    /// Waiting for the chip ran out of `PollConfig` attempts or the given timeout
+    Timeout,
+    /// `self_test` read an unknown device ID or an invalid Type-C revision
+    SelfTestFailed,
+    /// An argument is outside the range the chip or NVM field can hold
+    OutaRangeValue,
+    /// A PDO1 other than 5V was given, PDO1 must always be 5V
+    InvalidPdo1Voltage,
+    /// `probe_address` found no STUSB4500 at any candidate address
+    DeviceNotFound,
+    /// Driving the load switch pin failed in `negotiate_and_enable`
+    PinError,
+    /// NVM contents failed validation and were not programmed
+    InvalidNvm,
+    /// NVM contents read back failed the plausibility check
+    ImplausibleNvm,
+    /// NVM contents read back after programming differ from the written ones
+    NvmVerifyFailed,
+    /// Bus error while polling the busy NVM controller, typically the I2C
+    /// master timing out while the chip stretches the clock
+    BusTimeout(I2C),
+    /// A read returned fewer bytes than requested
+    ShortRead
+}
+
+/// Result of the driver methods, with `E` the error type of the I2C bus.
+///
+/// Saves naming `Error<E>` in wrapper functions:
+///
+/// ```
+/// use embedded_hal::blocking::i2c;
+/// use stusb4500::{Stusb4500Result, STUSB4500};
+///
+/// fn vbus_mv<I2C, E>(stusb: &mut STUSB4500<I2C>) -> Stusb4500Result<Option<u32>, E>
+///     where I2C: i2c::Write<Error = E> + i2c::Read<Error = E> {
+///     Ok(stusb.get_contract()?.voltage_mv)
+/// }
+/// ```
+pub type Stusb4500Result<T, E> = Result<T, Error<E>>;
+
+/// Converts bus errors into driver errors so `?` can be used on raw I2C calls.
+///
+/// A blanket `From<E> for Error<E>` is not possible as it would overlap with
+/// `From<T> for T`, so results are mapped explicitly instead.
+pub trait MapI2c<T, E> {
+    fn map_i2c(self) -> Stusb4500Result<T, E>;
+}
+
+impl<T, E> MapI2c<T, E> for Result<T, E> {
+    fn map_i2c(self) -> Stusb4500Result<T, E> {
+        self.map_err(Error::I2CError)
+    }
 }
 
+/// Timing for operations that poll the chip until a condition is met
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollConfig {
+    /// Maximum number of status reads before giving up with `Error::Timeout`
+    pub max_attempts: u32,
+    /// Delay between status reads in microseconds, `None` to poll back-to-back
+    pub interval_us: Option<u32>,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            max_attempts: 100,
+            interval_us: Some(1_000),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PdoChannel {
     PDO1,
     PDO2,
     PDO3
 }
 
+impl PdoChannel {
+    /// All channels in order
+    pub const ALL: [PdoChannel; 3] = [PdoChannel::PDO1, PdoChannel::PDO2, PdoChannel::PDO3];
+}
+
+/// Number of sink PDOs advertised by `set_profile_mode`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileMode {
+    /// Only PDO1 (5V)
+    Minimal,
+    /// All three PDOs
+    Full,
+}
+
 pub struct STUSB4500<I2C> {
     i2c: I2C,
-    address: u8
+    address: u8,
+    poll: PollConfig,
+    /// Contract seen by the last `contract_changed` call
+    last_contract: Option<Contract>
 }
 
 impl<I2C, E> STUSB4500<I2C>
@@ -67,38 +191,242 @@ impl<I2C, E> STUSB4500<I2C>
     pub fn new(i2c: I2C, address: Address) -> Self {
         STUSB4500 {
             i2c,
-            address: address.addr()
+            address: address.addr(),
+            poll: PollConfig::default(),
+            last_contract: None
+        }
+    }
+
+    /// Find the address of a STUSB4500 by reading the device ID at each candidate.
+    /// Returns the first candidate that responds with a known ID, which detects
+    /// the strapped address of a board.
+    pub fn probe_address(i2c: &mut I2C, candidates: &[Address]) -> Stusb4500Result<Address, E> {
+        for candidate in candidates {
+            let mut id = [0x00];
+            let found = i2c.write(candidate.addr(), &[Register::DeviceId as u8])
+                .and_then(|_| i2c.read(candidate.addr(), &mut id))
+                .is_ok();
+            if found && STUSB4500_DEVICE_IDS.contains(&id[0]) {
+                return Ok(*candidate);
+            }
         }
+        Err(Error::DeviceNotFound)
+    }
+
+    /// Set the default poll timing used when an operation is not given one
+    pub fn set_poll_config(&mut self, config: PollConfig) {
+        self.poll = config;
+    }
+
+    /// Get the default poll timing
+    pub fn poll_config(&self) -> PollConfig {
+        self.poll
+    }
+
+    /// Get the VBUS monitoring status
+    pub fn get_monitoring_status(&mut self) -> Stusb4500Result<MonitoringStatus, E> {
+        Ok(MonitoringStatus::from_bits_truncate(self.read(Register::TypeCMonitoringStatus1)?))
+    }
+
+    /// Get the CC line status
+    pub fn get_cc_status(&mut self) -> Stusb4500Result<CcStatus, E> {
+        self.read_into(Register::CCStatus)
+    }
+
+    /// Get the raw states of CC1 and CC2, for debugging cables and terminations
+    pub fn get_cc_line_states(&mut self) -> Stusb4500Result<(CcState, CcState), E> {
+        Ok(self.get_cc_status()?.line_states())
+    }
+
+    /// Check if the chip is toggling, looking for a connection on a detached port
+    pub fn is_looking_for_connection(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_cc_status()?.looking_for_connection())
+    }
+
+    /// Get the PD/Type-C hard reset handshake status
+    pub fn get_pd_typec_status(&mut self) -> Stusb4500Result<PdTypeCStatus, E> {
+        self.read_into(Register::PDTypeCStatus)
+    }
+
+    /// Get the Type-C status
+    pub fn get_typec_status(&mut self) -> Stusb4500Result<TypeCStatus, E> {
+        self.read_into(Register::TypeCStatus)
+    }
+
+    /// Wait until VBUS is ready, using the driver's poll timing unless `config` overrides it
+    pub fn wait_for_vbus_ready<D: DelayUs<u32>>(&mut self, delay: &mut D, config: Option<PollConfig>) -> Stusb4500Result<(), E> {
+        self.poll_until(delay, config, |dev| {
+            Ok(dev.get_monitoring_status()?.contains(MonitoringStatus::VbusReady))
+        })
     }
 
     /// Read all interrupt registers to clear them
-    pub fn clear_interrupts(&mut self) -> Result<(), Error<E>>{
+    pub fn clear_interrupts(&mut self) -> Stusb4500Result<(), E>{
         // Read all interrupt registers
         let mut _buf = [0x00; 10];
-        self.i2c.write(self.address, &[Register::PortStatus0 as u8]).map_err(|err| Error::I2CError(err))?;
-        self.i2c.read(self.address, &mut _buf).map_err(|err| Error::I2CError(err))
+        self.i2c.write(self.address, &[Register::PortStatus0 as u8]).map_i2c()?;
+        self.i2c.read(self.address, &mut _buf).map_i2c()
+    }
+
+    /// Handle an interrupt: read the alert and only the status registers it flags,
+    /// which also clears their transition bits.
+    pub fn handle_interrupt(&mut self) -> Stusb4500Result<InterruptSnapshot, E> {
+        let alert = self.get_alerts()?;
+        let mut snapshot = InterruptSnapshot {
+            alert,
+            port_status: None,
+            monitoring_status: None,
+            cc_hw_fault_status: None,
+            pd_typec_status: None,
+            prt_status: None,
+        };
+        let mut buf = [0x00; 2];
+        if alert.contains(Alert::PortStatus) {
+            self.read_regs(Register::PortStatus0, &mut buf)?;
+            snapshot.port_status = Some(buf);
+        }
+        if alert.contains(Alert::TypeCMonitoringStatus) {
+            self.read_regs(Register::TypeCMonitoringStatus0, &mut buf)?;
+            snapshot.monitoring_status = Some(buf);
+        }
+        if alert.contains(Alert::CCHWFaultStatus) {
+            self.read_regs(Register::CCHWFaultStatus0, &mut buf)?;
+            snapshot.cc_hw_fault_status = Some(buf);
+        }
+        if alert.contains(Alert::PDTypeCStatus) {
+            snapshot.pd_typec_status = Some(self.read(Register::PDTypeCStatus)?);
+        }
+        if alert.contains(Alert::PRTStatus) {
+            snapshot.prt_status = Some(self.get_prt_status()?);
+        }
+        Ok(snapshot)
     }
 
     /// Set interrupt mask
-    pub fn set_alerts_mask(&mut self, alerts: AlertMask) -> Result<(), Error<E>>{
+    pub fn set_alerts_mask(&mut self, alerts: AlertMask) -> Stusb4500Result<(), E>{
         self.write(Register::AlertStatus1Mask, alerts.bits())
     }
 
+    /// Unmask the given alerts, leaving the rest of the mask untouched.
+    /// A set bit in the mask register masks the corresponding alert.
+    pub fn enable_alerts(&mut self, alerts: AlertMask) -> Stusb4500Result<(), E>{
+        let mask = self.read(Register::AlertStatus1Mask)?;
+        self.write(Register::AlertStatus1Mask, mask & !alerts.bits())
+    }
+
+    /// Mask the given alerts, leaving the rest of the mask untouched
+    pub fn disable_alerts(&mut self, alerts: AlertMask) -> Stusb4500Result<(), E>{
+        let mask = self.read(Register::AlertStatus1Mask)?;
+        self.write(Register::AlertStatus1Mask, mask | alerts.bits())
+    }
+
     /// Get active interrupt flags
-    pub fn get_alerts(&mut self) -> Result<Alert, Error<E>>{
+    pub fn get_alerts(&mut self) -> Stusb4500Result<Alert, E>{
         Ok(Alert::from_masked_bits(self.read(Register::AlertStatus1)?))
     }
 
+    /// Check if any alert that is not masked in `ALERT_STATUS_1_MASK` is set,
+    /// i.e. if the ALERT pin is asserted. Reading the status does not clear it.
+    pub fn any_alert_pending(&mut self) -> Stusb4500Result<bool, E> {
+        let status = Alert::from_masked_bits(self.read(Register::AlertStatus1)?);
+        let mask = self.read(Register::AlertStatus1Mask)?;
+        Ok(status.bits() & !mask != 0)
+    }
+
+    /// Send a PD control message to the source
+    pub fn send_pd_command(&mut self, message: PdControlMessage) -> Stusb4500Result<(), E> {
+        self.write(Register::TXHeaderL, message as u8)?;
+        self.write(Register::PDCommandCtrl, 0x26)
+    }
+
     /// Perform a soft reset
     /// Triggers re-negotiation of PDO's.
-    pub fn soft_reset(&mut self) -> Result<(), Error<E>> {
-        self.write(Register::TXHeaderL, 0x0D)?;
-        self.write(Register::PDCommandCtrl, 0x26)?;
+    pub fn soft_reset(&mut self) -> Stusb4500Result<(), E> {
+        trace!("soft_reset");
+        self.send_pd_command(PdControlMessage::SoftReset)?;
+        trace!("soft_reset: done");
+        Ok(())
+    }
+
+    /// Send Get_Source_Cap and wait up to `timeout_ms` for the source to
+    /// answer, polling every millisecond. `PRT_STATUS` is read before sending
+    /// so a stale `MsgReceived` is not taken as the answer, reading it also
+    /// clears its other bits.
+    pub fn request_source_capabilities<D: DelayMs<u16>>(&mut self, delay: &mut D, timeout_ms: u16) -> Stusb4500Result<heapless::Vec<Pdo, 7>, E> {
+        self.get_prt_status()?;
+        self.send_pd_command(PdControlMessage::GetSourceCap)?;
+        for elapsed in 0..=timeout_ms {
+            if elapsed > 0 {
+                delay.delay_ms(1);
+            }
+            if self.get_prt_status()?.contains(PrtStatus::MsgReceived) && self.has_source_capabilities()? {
+                return self.get_source_capabilities();
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Reset the chip through `SW_RESET` in `RESET_CTRL`, loading the NVM into
+    /// the live registers as after power-up. Unlike `soft_reset`, which only
+    /// renegotiates with the current live PDOs, this discards all host changes
+    /// to the live registers and the source sees a detach, so VBUS may drop.
+    /// The reset is held for the datasheet reset time.
+    pub fn reload_nvm<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Stusb4500Result<(), E> {
+        trace!("reload_nvm");
+        self.write(Register::ResetCtrl, 0x01)?;
+        delay.delay_ms(RESET_TIME_MS);
+        self.write(Register::ResetCtrl, 0x00)?;
+        self.last_contract = None;
+        trace!("reload_nvm: done");
         Ok(())
     }
 
-    pub fn set_pdo(&mut self, pdo: PdoChannel, data: &Pdo) -> Result<(), Error<E>> {
-        if let Pdo::Fixed {..} = data {
+    /// Software reset like `reload_nvm`, then wait for the chip to answer
+    /// with a known device ID. Returns `Error::Timeout` if it does not within
+    /// the driver's poll attempts, 1ms apart.
+    pub fn full_reset<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Stusb4500Result<(), E> {
+        trace!("full_reset");
+        self.reload_nvm(delay)?;
+        for _ in 0..self.poll.max_attempts {
+            // The chip may not acknowledge while still starting up
+            if let Ok(id) = self.read(Register::DeviceId) {
+                if STUSB4500_DEVICE_IDS.contains(&id) {
+                    return Ok(());
+                }
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Soft reset and check that the policy engine started renegotiating.
+    /// Returns false if it did not leave its state within the driver's poll
+    /// timing, e.g. because no PD source is attached.
+    pub fn soft_reset_checked<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<bool, E> {
+        self.soft_reset()?;
+        let result = self.poll_until(delay, None, |dev| {
+            Ok(matches!(dev.get_pe_state()?,
+                PolicyEngineState::SoftReset
+                | PolicyEngineState::SendSoftReset
+                | PolicyEngineState::SnkWaitForCapabilities
+                | PolicyEngineState::SnkEvaluateCapabilities
+                | PolicyEngineState::SnkSelectCapabilities
+                | PolicyEngineState::SnkTransitionSink))
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(Error::Timeout) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Set a sink PDO. Only fixed PDOs can be advertised and PDO1 must be 5V,
+    /// as required for USB-C sinks.
+    pub fn set_pdo(&mut self, pdo: PdoChannel, data: &Pdo) -> Stusb4500Result<(), E> {
+        if let Pdo::Fixed(fixed) = data {
+            if pdo == PdoChannel::PDO1 && fixed.voltage() != 100 {
+                return Err(Error::InvalidPdo1Voltage);
+            }
             self.write_word(match pdo {
                 PdoChannel::PDO1 => Register::DPMSNKPDO1,
                 PdoChannel::PDO2 => Register::DPMSNKPDO2,
@@ -106,11 +434,11 @@ impl<I2C, E> STUSB4500<I2C>
             }, data.bits())
         }else{
             // Can only advertise fixed PDOs
-            Err(Error::InvalidPdo)
+            Err(Error::NotFixedPdo)
         }
     }
     
-    pub fn get_pdo(&mut self, pdo: PdoChannel) -> Result<Pdo, Error<E>> {
+    pub fn get_pdo(&mut self, pdo: PdoChannel) -> Stusb4500Result<Pdo, E> {
         Pdo::from_bits(self.read_word(match pdo {
             PdoChannel::PDO1 => Register::DPMSNKPDO1,
             PdoChannel::PDO2 => Register::DPMSNKPDO2,
@@ -118,11 +446,240 @@ impl<I2C, E> STUSB4500<I2C>
         })?).ok_or(Error::InvalidPdo)
     }
 
-    pub fn get_current_rdo(&mut self) -> Result<Rdo, Error<E>>{
-        Ok(Rdo(self.read_word(Register::RDORegStatus)?))
+    pub fn get_current_rdo(&mut self) -> Stusb4500Result<Rdo, E>{
+        self.read_into(Register::RDORegStatus)
+    }
+
+    /// Check if the source could not satisfy any sink PDO
+    pub fn current_contract_has_mismatch(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_current_rdo()?.capability_mismatch())
+    }
+
+    /// Check the give back flag of the request, always false on the STUSB4500
+    pub fn current_contract_giveback(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_current_rdo()?.give_back())
+    }
+
+    /// Advertise only the first `n` PDOs without touching the stored PDO values,
+    /// so the remaining ones can be re-enabled later. Same as `set_num_pdo`.
+    pub fn with_active_pdos(&mut self, n: u8) -> Stusb4500Result<(), E> {
+        self.set_num_pdo(n)
+    }
+
+    /// Renegotiate requesting the PDO in channel `ch`.
+    /// The STUSB4500 always requests the highest active PDO the source can
+    /// satisfy, so this limits the active PDOs to `ch` and soft resets. The
+    /// contract falls back to a lower PDO if the source cannot provide `ch`.
+    pub fn request_pdo(&mut self, ch: PdoChannel) -> Stusb4500Result<(), E> {
+        self.set_num_pdo(match ch {
+            PdoChannel::PDO1 => 1,
+            PdoChannel::PDO2 => 2,
+            PdoChannel::PDO3 => 3,
+        })?;
+        self.soft_reset()
+    }
+
+    /// Switch between advertising only 5V and all three PDOs, and
+    /// renegotiate. The PDO values are preserved, only the count changes.
+    pub fn set_profile_mode(&mut self, mode: ProfileMode) -> Stusb4500Result<(), E> {
+        self.set_num_pdo(match mode {
+            ProfileMode::Minimal => 1,
+            ProfileMode::Full => 3,
+        })?;
+        self.soft_reset()
+    }
+
+    /// Advertise the common 5V/9V/15V profile with `current_ma` (at most
+    /// 5000) on all three PDOs and renegotiate. Does not wait for the new
+    /// contract.
+    pub fn configure_standard_profile(&mut self, current_ma: u32) -> Stusb4500Result<(), E> {
+        if current_ma > 5000 {
+            return Err(Error::OutaRangeValue);
+        }
+        let current = (current_ma / 10) as u16;
+        self.set_pdo(PdoChannel::PDO1, &Pdo::new_fixed(100, current))?;
+        self.set_pdo(PdoChannel::PDO2, &Pdo::new_fixed(180, current))?;
+        self.set_pdo(PdoChannel::PDO3, &Pdo::new_fixed(300, current))?;
+        self.set_num_pdo(3)?;
+        self.soft_reset()
+    }
+
+    /// Stop advertising the active PDOs above `max_mv` and renegotiate.
+    /// Reduces the number of PDOs to the ones up to the first PDO above
+    /// `max_mv`, the PDO values are kept. Never increases the number of PDOs.
+    pub fn limit_max_voltage(&mut self, max_mv: u32) -> Stusb4500Result<(), E> {
+        let num = self.get_live_num_pdo()?.clamp(1, 3);
+        let mut allowed = 0;
+        for ch in PdoChannel::ALL.iter().take(num as usize) {
+            match self.get_pdo(*ch)? {
+                Pdo::Fixed(pdo) if pdo.voltage() * 50 <= max_mv => allowed += 1,
+                _ => break,
+            }
+        }
+        if allowed == 0 {
+            return Err(Error::OutaRangeValue);
+        }
+        self.set_num_pdo(allowed)?;
+        self.soft_reset()
+    }
+
+    /// Advertise `pdos` (1-3 fixed PDOs), optionally set the flexible current
+    /// (at most 5000mA, in 10mA steps) and renegotiate. There is no live
+    /// flexible current, so it is written to the NVM and only takes effect
+    /// after the next reset, see `reload_nvm`. Only the changed NVM sector is
+    /// reprogrammed, see `write_nvm_diff`. Everything, including the new
+    /// NVM contents, is validated before the first write, and the NVM is
+    /// programmed before the live PDOs so a failed NVM access changes nothing.
+    pub fn apply_profile<D: DelayUs<u32>>(&mut self, pdos: &[Pdo], flex_current_ma: Option<u32>, delay: &mut D) -> Stusb4500Result<(), E> {
+        if pdos.is_empty() || pdos.len() > 3 {
+            return Err(Error::OutaRangePdo);
+        }
+        for (ch, pdo) in PdoChannel::ALL.iter().zip(pdos) {
+            match pdo {
+                Pdo::Fixed(fixed) if *ch == PdoChannel::PDO1 && fixed.voltage() != 100 => {
+                    return Err(Error::InvalidPdo1Voltage);
+                },
+                Pdo::Fixed(_) => {},
+                _ => return Err(Error::NotFixedPdo),
+            }
+        }
+        if flex_current_ma.map_or(false, |ma| ma > 5000 || ma % 10 != 0) {
+            return Err(Error::OutaRangeValue);
+        }
+        if let Some(ma) = flex_current_ma {
+            let current = self.get_nvm_config(delay)?;
+            let mut config = current;
+            config.set_flex_current(ma / 10);
+            self.nvm_program_changes(&current, &config, delay)?;
+        }
+        for (ch, pdo) in PdoChannel::ALL.iter().zip(pdos) {
+            self.set_pdo(*ch, pdo)?;
+        }
+        self.set_num_pdo(pdos.len() as u8)?;
+        self.soft_reset()
+    }
+
+    /// Advertise `pdos` (1-3 fixed PDOs), renegotiate and wait for VBUS to be
+    /// ready using the driver's poll timing. Returns the new contract.
+    /// `VBUS_READY` may still be set from the old contract, so it is only
+    /// checked once the policy engine is back in an explicit contract.
+    pub fn negotiate<D: DelayUs<u32>>(&mut self, pdos: &[Pdo], delay: &mut D) -> Stusb4500Result<Contract, E> {
+        trace!("negotiate: {:?}", pdos);
+        if pdos.is_empty() || pdos.len() > 3 {
+            return Err(Error::OutaRangePdo);
+        }
+        for (ch, pdo) in PdoChannel::ALL.iter().zip(pdos) {
+            self.set_pdo(*ch, pdo)?;
+        }
+        self.set_num_pdo(pdos.len() as u8)?;
+        self.soft_reset()?;
+        self.poll_until(delay, None, |dev| dev.has_explicit_contract())?;
+        self.wait_for_vbus_ready(delay, None)?;
+        let contract = self.get_contract()?;
+        trace!("negotiate: done, {:?}", contract);
+        Ok(contract)
+    }
+
+    /// Negotiate like `negotiate`, then drive the load switch `enable` high.
+    /// The pin is driven low if anything fails, so the load is only powered
+    /// once VBUS is ready. A failure to drive it low is ignored in favour of
+    /// the negotiation error.
+    pub fn negotiate_and_enable<D, P>(&mut self, pdos: &[Pdo], enable: &mut P, delay: &mut D) -> Stusb4500Result<Contract, E>
+        where D: DelayUs<u32>, P: OutputPin {
+        match self.negotiate(pdos, delay) {
+            Ok(contract) => {
+                enable.set_high().map_err(|_| Error::PinError)?;
+                Ok(contract)
+            },
+            Err(err) => {
+                let _ = enable.set_low();
+                Err(err)
+            },
+        }
+    }
+
+    /// Check if the contract provides the highest active sink PDO.
+    /// False if the source only satisfied a lower PDO or flagged a capability
+    /// mismatch. When the contract voltage is unknown only the mismatch flag
+    /// is checked.
+    pub fn contract_satisfied(&mut self) -> Stusb4500Result<bool, E> {
+        let contract = self.get_contract()?;
+        if contract.rdo.capability_mismatch() {
+            return Ok(false);
+        }
+        let num = self.get_live_num_pdo()?.clamp(1, 3) as usize;
+        let top = match self.get_pdo(PdoChannel::ALL[num - 1])? {
+            Pdo::Fixed(pdo) => pdo.voltage() * 50,
+            _ => return Err(Error::InvalidPdo),
+        };
+        Ok(contract.voltage_mv.map_or(true, |mv| mv >= top))
+    }
+
+    /// Check an explicit contract is in place and its voltage is within
+    /// `tolerance_mv` of `expected_mv`
+    pub fn verify_voltage(&mut self, expected_mv: u32, tolerance_mv: u32) -> Stusb4500Result<bool, E> {
+        if !self.has_explicit_contract()? {
+            return Ok(false);
+        }
+        Ok(self.get_contract()?.voltage_within(expected_mv, tolerance_mv))
+    }
+
+    /// Get the highest power in mW among the active PDOs, i.e. the most this
+    /// chip would ask for with its current configuration
+    pub fn max_configured_power_mw(&mut self) -> Stusb4500Result<u32, E> {
+        let num = self.get_live_num_pdo()? as usize;
+        let mut max = 0;
+        for ch in PdoChannel::ALL.iter().take(num) {
+            max = max.max(self.get_pdo(*ch)?.power_mw());
+        }
+        Ok(max)
+    }
+
+    /// Get the operating current granted by the source in mA, and whether the
+    /// sink flagged a capability mismatch, i.e. no sink PDO could be satisfied
+    /// by the source
+    pub fn effective_current_ma(&mut self) -> Stusb4500Result<(u32, bool), E> {
+        let rdo = self.get_current_rdo()?;
+        Ok((rdo.operating_current() * 10, rdo.capability_mismatch()))
+    }
+
+    /// Get the current in mA that may be drawn from the source: the RDO max
+    /// current with an explicit PD contract, otherwise the Type-C Rp
+    /// advertisement on the attached CC line.
+    pub fn available_current_ma(&mut self) -> Stusb4500Result<u32, E> {
+        if self.has_explicit_contract()? {
+            return Ok(self.get_current_rdo()?.max_operating_current() * 10);
+        }
+        Ok(self.get_cc_status()?.source_current().map_or(0, |c| c.current_ma()))
+    }
+
+    /// Check if USB data communication is supported on the current contract
+    pub fn usb_comm_capable(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_current_rdo()?.usb_communication_capable())
+    }
+
+    /// Get the current state of the PD policy engine
+    pub fn get_pe_state(&mut self) -> Stusb4500Result<PolicyEngineState, E> {
+        Ok(PolicyEngineState::from(self.read(Register::PEFSM)?))
+    }
+
+    /// Check if an explicit PD contract is in place, as opposed to only default
+    /// USB/Type-C 5V power. The policy engine only reaches the ready states
+    /// after the source has accepted a request.
+    pub fn has_explicit_contract(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(matches!(self.get_pe_state()?,
+            PolicyEngineState::SnkReady | PolicyEngineState::SnkReadySending))
     }
 
-    pub fn set_num_pdo(&mut self, num: u8) -> Result<(), Error<E>>{
+    /// Check if the attached source talks PD, i.e. a PD contract is in place or
+    /// Source_Capabilities were received. False for Type-C only chargers.
+    pub fn source_is_pd_capable(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.has_explicit_contract()? || self.has_source_capabilities()?)
+    }
+
+    /// Set the number of sink PDOs advertised (1-3). Only the first `num`
+    /// PDO registers are used, the values of the others are preserved.
+    pub fn set_num_pdo(&mut self, num: u8) -> Stusb4500Result<(), E>{
         match num {
             1..=3 => {
                 self.write(Register::DPMPDONumb, num)
@@ -130,50 +687,2406 @@ impl<I2C, E> STUSB4500<I2C>
             _ => Err(Error::OutaRangePdo)
         }
     }
-    // *****************************************************************
-    // Raw access functions
 
-    /// Write a byte register
-    pub(crate) fn write(&mut self, register: Register, value: u8) -> Result<(), Error<E>> {
-        let buf = [register as u8, value];
-        self.i2c.write(self.address, &buf).map_err(|err| Error::I2CError(err))
+    /// Get the protocol layer status
+    pub fn get_prt_status(&mut self) -> Stusb4500Result<PrtStatus, E> {
+        Ok(PrtStatus::from_bits_truncate(self.read(Register::PRTStatus)?))
     }
 
-    /// Write a word register
-    pub(crate) fn write_word(&mut self, register: Register, word: u32) -> Result<(), Error<E>> {
-        let mut buf = [0x00; 5];
-        buf[0] = register as u8;
-        LittleEndian::write_u32(&mut buf[1..], word);
-        self.i2c.write(self.address, &buf).map_err(|err| Error::I2CError(err))
+    /// Check if the protocol layer alert is pending, i.e. `PRT_STATUS` holds an
+    /// event such as a received PD message. Does not clear the alert, the
+    /// event may also be a hard reset or BIST message.
+    pub fn pd_message_pending(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_alerts()?.contains(Alert::PRTStatus))
     }
 
-    /// Read a byte register
-    pub(crate) fn read(&mut self, register: Register) -> Result<u8, Error<E>> {
-        let mut buf = [0x00; 1];
-        self.i2c.write(self.address, &[register as u8]).map_err(|err| Error::I2CError(err))?;
-        self.i2c.read(self.address, &mut buf).map_err(|err| Error::I2CError(err))?;
-        Ok(buf[0])
+    /// Clear the protocol layer alert by reading `PRT_STATUS`.
+    /// Returns true if the event was a received PD message.
+    pub fn clear_pd_message(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_prt_status()?.contains(PrtStatus::MsgReceived))
     }
 
-    /// Read a word register
-    pub(crate) fn read_word(&mut self, register: Register) -> Result<u32, Error<E>> {
-        let mut buf = [0x00; 4];
-        self.i2c.write(self.address, &[register as u8]).map_err(|err| Error::I2CError(err))?;
-        self.i2c.read(self.address, &mut buf).map_err(|err| Error::I2CError(err))?;
-        Ok(LittleEndian::read_u32(&buf))
+    /// Check if the source has issued a PD hard reset.
+    /// Decoded from the `PRL_HW_RST_RECEIVED` bit (bit 0) of `PRT_STATUS` (0x16).
+    pub fn hard_reset_in_progress(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_prt_status()?.contains(PrtStatus::HwResetReceived))
     }
 
-}
+    /// Get the device ID.
+    /// The ID is never zero, so a zero read means the buffer was not filled
+    /// and is reported as `Error::ShortRead`.
+    pub fn get_device_id(&mut self) -> Stusb4500Result<u8, E> {
+        match self.read(Register::DeviceId)? {
+            0x00 => Err(Error::ShortRead),
+            id => Ok(id),
+        }
+    }
 
+    /// Check that the chip is alive and addressable.
+    /// The device ID must be a known STUSB4500 ID and the Type-C revision must
+    /// read back as a valid, non-zero BCD value.
+    pub fn self_test(&mut self) -> Stusb4500Result<(), E> {
+        if !STUSB4500_DEVICE_IDS.contains(&self.get_device_id()?) {
+            return Err(Error::SelfTestFailed);
+        }
+        let mut rev = [0x00; 2];
+        self.read_regs(Register::BcdTypeCRevL, &mut rev)?;
+        let bcd = rev.iter().all(|b| b & 0x0F <= 9 && b >> 4 <= 9);
+        if !bcd || rev == [0x00; 2] {
+            return Err(Error::SelfTestFailed);
+        }
+        Ok(())
+    }
 
+    /// Get the number of sink PDOs currently in use from `DPM_PDO_NUMB`
+    pub fn get_live_num_pdo(&mut self) -> Stusb4500Result<u8, E> {
+        Ok(self.read(Register::DPMPDONumb)? & 0x07)
+    }
+
+    /// Read the whole `DPM_PDO_NUMB` byte. It holds only the PDO count in
+    /// bits 2:0 (`DPM_SNK_PDO_NUMB`), bits 7:3 are reserved and read as 0, so
+    /// anything set there points at a bus or register map problem.
+    pub fn get_dpm_pdo_numb_raw(&mut self) -> Stusb4500Result<u8, E> {
+        self.read(Register::DPMPDONumb)
+    }
 
+    /// Get the number of sink PDOs stored in NVM, loaded into `DPM_PDO_NUMB` on reset
+    pub fn get_nvm_num_pdo<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<u8, E> {
+        Ok(self.get_nvm_config(delay)?.num_pdo())
+    }
 
+    /// Read and decode the NVM
+    pub fn get_nvm_config<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<NvmConfig, E> {
+        Ok(NvmConfig::from_bytes(self.get_nvm_bytes(delay)?))
+    }
 
+    /// Read and decode the NVM like `get_nvm_config`, returning
+    /// `Error::ImplausibleNvm` if the read fails `NvmConfig::is_valid`
+    pub fn get_nvm_config_checked<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<NvmConfig, E> {
+        let config = self.get_nvm_config(delay)?;
+        if !config.is_valid() {
+            return Err(Error::ImplausibleNvm);
+        }
+        Ok(config)
+    }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    /// Check if the live sink PDOs and `DPM_PDO_NUMB` still hold the values
+    /// loaded from NVM on reset. False if the host has overridden them since.
+    /// Only the voltage and current of the PDOs are compared.
+    pub fn nvm_matches_live<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<bool, E> {
+        Ok(self.pdo_config_report(delay)?.consistent)
+    }
+
+    /// Read the sink PDO configuration from NVM and from the live registers
+    pub fn pdo_config_report<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<PdoConfigReport, E> {
+        let nvm = self.get_nvm_config(delay)?;
+        let live_num_pdo = self.get_live_num_pdo()?;
+        let mut nvm_pdos = [Pdo::new_fixed(0, 0); 3];
+        let mut live_pdos = nvm_pdos;
+        let mut consistent = nvm.num_pdo() == live_num_pdo;
+        for (i, ch) in PdoChannel::ALL.iter().enumerate() {
+            let voltage = nvm.pdo_voltage(*ch);
+            let current = nvm.pdo_current(*ch);
+            nvm_pdos[i] = Pdo::new_fixed(voltage as u16, current as u16);
+            live_pdos[i] = self.get_pdo(*ch)?;
+            consistent &= match live_pdos[i] {
+                Pdo::Fixed(pdo) => pdo.voltage() == voltage && pdo.current() == current,
+                _ => false,
+            };
+        }
+        Ok(PdoConfigReport { nvm_num_pdo: nvm.num_pdo(), live_num_pdo, nvm_pdos, live_pdos, consistent })
+    }
+
+    /// Read the NVM sectors, each packed as a little-endian u64.
+    /// The first byte of a sector on the wire is the least significant byte,
+    /// independent of the host byte order.
+    pub fn get_nvm<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u64; NVM_SECTORS], E> {
+        let bytes = self.get_nvm_bytes(delay)?;
+        let mut sectors = [0u64; NVM_SECTORS];
+        LittleEndian::read_u64_into(&bytes, &mut sectors);
+        Ok(sectors)
+    }
+
+    /// Read the NVM as bytes, in sector order
+    pub fn get_nvm_bytes<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u8; NVM_SIZE], E> {
+        self.unlock_nvm()?;
+        let bytes = self.nvm_read_all(delay);
+        if bytes.is_err() {
+            // Best effort to leave the NVM locked
+            let _ = self.lock_nvm();
+            return bytes;
+        }
+        self.lock_nvm()?;
+        bytes
+    }
+
+    /// Read the NVM in on-wire byte order: sector 0 byte 0 first, exactly as
+    /// the bytes are transferred and as listed in ST's GUI export.
+    pub fn get_nvm_wire_bytes<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u8; NVM_SIZE], E> {
+        self.get_nvm_bytes(delay)
+    }
+
+    /// Program the NVM from bytes in on-wire byte order, see `get_nvm_wire_bytes`
+    pub fn set_nvm_wire_bytes<D: DelayUs<u32>>(&mut self, bytes: &[u8; NVM_SIZE], delay: &mut D) -> Stusb4500Result<(), E> {
+        self.write_nvm_bytes(bytes, delay)
+    }
+
+    /// Read the NVM sectors without unlocking/locking it.
+    /// Use between `unlock_nvm` and `lock_nvm` to batch several NVM operations.
+    pub fn get_nvm_raw<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u64; NVM_SECTORS], E> {
+        let bytes = self.nvm_read_all(delay)?;
+        let mut sectors = [0u64; NVM_SECTORS];
+        LittleEndian::read_u64_into(&bytes, &mut sectors);
+        Ok(sectors)
+    }
+
+    /// Program the NVM sectors, each packed as a little-endian u64
+    pub fn write_nvm<D: DelayUs<u32>>(&mut self, sectors: [u64; NVM_SECTORS], delay: &mut D) -> Stusb4500Result<(), E> {
+        let mut bytes = [0x00; NVM_SIZE];
+        LittleEndian::write_u64_into(&sectors, &mut bytes);
+        self.write_nvm_bytes(&bytes, delay)
+    }
+
+    /// Program the NVM sectors like `write_nvm`, without validating them
+    pub fn write_nvm_unchecked<D: DelayUs<u32>>(&mut self, sectors: [u64; NVM_SECTORS], delay: &mut D) -> Stusb4500Result<(), E> {
+        let mut bytes = [0x00; NVM_SIZE];
+        LittleEndian::write_u64_into(&sectors, &mut bytes);
+        self.nvm_program(&bytes, delay)
+    }
+
+    /// Erase and program the whole NVM, bytes in sector order.
+    /// The new values are loaded into the live registers on the next reset.
+    /// Waits for the policy engine to be idle first using the driver's poll
+    /// timing, but unplugging the source before programming is still
+    /// recommended. Returns `Error::InvalidNvm`
+    /// without programming if `NvmConfig::is_valid` rejects the bytes.
+    pub fn write_nvm_bytes<D: DelayUs<u32>>(&mut self, bytes: &[u8; NVM_SIZE], delay: &mut D) -> Stusb4500Result<(), E> {
+        if !NvmConfig::from_bytes(*bytes).is_valid() {
+            return Err(Error::InvalidNvm);
+        }
+        self.nvm_program(bytes, delay)
+    }
+
+    /// Program the NVM like `write_nvm_bytes` and return the programmed bytes
+    /// for audit logs. The bytes are never normalized, so they always equal
+    /// `bytes`.
+    pub fn write_nvm_bytes_logged<D: DelayUs<u32>>(&mut self, bytes: &[u8; NVM_SIZE], delay: &mut D) -> Stusb4500Result<[u8; NVM_SIZE], E> {
+        self.write_nvm_bytes(bytes, delay)?;
+        Ok(*bytes)
+    }
+
+    /// Set the VBUS monitoring thresholds of a PDO in the NVM, in percent (5-20%)
+    /// below and above the PDO voltage with a 1% LSB. The NVM only stores these
+    /// relative windows, not absolute voltages. PDO1 has no lower threshold, its
+    /// `low_pct` is ignored.
+    pub fn set_vbus_alarm_thresholds<D: DelayUs<u32>>(&mut self, ch: PdoChannel, low_pct: u8, high_pct: u8, delay: &mut D) -> Stusb4500Result<(), E> {
+        if !(5..=20).contains(&low_pct) || !(5..=20).contains(&high_pct) {
+            return Err(Error::OutaRangeValue);
+        }
+        let mut config = self.get_nvm_config(delay)?;
+        config.set_voltage_lower_limit(ch, low_pct);
+        config.set_voltage_upper_limit(ch, high_pct);
+        self.write_nvm_bytes(&config.to_bytes(), delay)
+    }
+
+    /// Program `desired` into the NVM only if it differs from the current
+    /// contents, to avoid needless NVM wear. Returns true if it was written.
+    pub fn ensure_nvm_config<D: DelayUs<u32>>(&mut self, desired: &NvmConfig, delay: &mut D) -> Stusb4500Result<bool, E> {
+        if self.get_nvm_config(delay)? == *desired {
+            return Ok(false);
+        }
+        self.write_nvm_bytes(&desired.to_bytes(), delay)?;
+        Ok(true)
+    }
+
+    /// Program only the NVM sectors where `desired` differs from the current
+    /// contents. The sector erase is sector granular, so the other sectors are
+    /// neither erased nor rewritten. Returns the bit mask of the programmed
+    /// sectors, 0 if nothing changed, or `Error::InvalidNvm` like
+    /// `write_nvm_bytes`.
+    pub fn write_nvm_diff<D: DelayUs<u32>>(&mut self, desired: &NvmConfig, delay: &mut D) -> Stusb4500Result<u8, E> {
+        let current = self.get_nvm_config(delay)?;
+        self.nvm_program_changes(&current, desired, delay)
+    }
+
+    /// Program the sectors where `desired` differs from `current`
+    fn nvm_program_changes<D: DelayUs<u32>>(&mut self, current: &NvmConfig, desired: &NvmConfig, delay: &mut D) -> Stusb4500Result<u8, E> {
+        if !desired.is_valid() {
+            return Err(Error::InvalidNvm);
+        }
+        let sectors = desired.changed_sectors(current);
+        if sectors != 0 {
+            self.nvm_program_sectors(&desired.to_bytes(), sectors, delay)?;
+        }
+        Ok(sectors)
+    }
+
+    /// Change one default sink PDO in the NVM, leaving everything else as is.
+    /// The current is rounded down to the nearest NVM current code, see
+    /// `NvmConfig::set_pdo_current`. Only the PDO sectors are reprogrammed,
+    /// see `write_nvm_diff`. The NVM is read back afterwards and
+    /// `Error::NvmVerifyFailed` returned if it does not hold the new PDO.
+    pub fn set_nvm_pdo<D: DelayUs<u32>>(&mut self, ch: PdoChannel, pdo: Pdo, delay: &mut D) -> Stusb4500Result<(), E> {
+        let fixed = match pdo {
+            Pdo::Fixed(fixed) => fixed,
+            _ => return Err(Error::NotFixedPdo),
+        };
+        if ch == PdoChannel::PDO1 && fixed.voltage() != 100 {
+            return Err(Error::InvalidPdo1Voltage);
+        }
+        let current = self.get_nvm_config(delay)?;
+        let mut config = current;
+        config.set_pdo_voltage(ch, fixed.voltage());
+        config.set_pdo_current(ch, fixed.current());
+        if self.nvm_program_changes(&current, &config, delay)? != 0
+            && self.get_nvm_config(delay)? != config {
+            return Err(Error::NvmVerifyFailed);
+        }
+        Ok(())
+    }
+
+    /// Check if the NVM is locked, without changing the lock state.
+    /// It is unlocked while `FTP_CUST_PASSWORD` holds the password and the
+    /// NVM controller is powered, as left by `unlock_nvm`.
+    pub fn is_nvm_locked(&mut self) -> Stusb4500Result<bool, E> {
+        let password = self.read(Register::FTPCustPassword)?;
+        let ctrl = self.read(Register::FTPCtrl0)?;
+        Ok(password != FTP_CUST_PASSWORD || ctrl & FTP_CUST_PWR == 0)
+    }
+
+    /// Unlock the NVM for several operations, it is locked again when the
+    /// returned session is dropped
+    pub fn nvm_session(&mut self) -> Stusb4500Result<NvmSession<'_, I2C>, E> {
+        self.unlock_nvm()?;
+        Ok(NvmSession { dev: self })
+    }
+
+    /// Unlock the NVM and power up its controller
+    pub fn unlock_nvm(&mut self) -> Stusb4500Result<(), E> {
+        self.write(Register::FTPCustPassword, FTP_CUST_PASSWORD)?;
+        // Reset the NVM controller
+        self.write(Register::FTPCtrl0, 0x00)?;
+        self.write(Register::FTPCtrl0, FTP_CUST_PWR | FTP_CUST_RST_N)
+    }
+
+    /// Clear the NVM controls and lock the NVM again
+    pub fn lock_nvm(&mut self) -> Stusb4500Result<(), E> {
+        self.write_regs(Register::FTPCtrl0, &[FTP_CUST_RST_N, 0x00])?;
+        self.write(Register::FTPCustPassword, 0x00)
+    }
+
+    /// Get all active faults, empty if nothing is wrong
+    pub fn get_faults(&mut self) -> Stusb4500Result<FaultFlags, E> {
+        let hw_fault = self.read(Register::CCHWFaultStatus1)?;
+        let monitoring = self.read(Register::TypeCMonitoringStatus0)?;
+        let mut faults = FaultFlags::empty();
+        faults.set(FaultFlags::VbusDischargeFault, hw_fault & 0x10 != 0);
+        faults.set(FaultFlags::CCOverVoltage, hw_fault & 0x80 != 0);
+        faults.set(FaultFlags::VbusOverVoltage, monitoring & 0x20 != 0);
+        faults.set(FaultFlags::VbusUnderVoltage, monitoring & 0x10 != 0);
+        Ok(faults)
+    }
+
+    /// Get VBUS relative to the monitoring thresholds.
+    /// Reading `TYPEC_MONITORING_STATUS_0` clears its transition bits.
+    pub fn get_vbus_band(&mut self) -> Stusb4500Result<VbusBand, E> {
+        let mut buf = [0x00; 2];
+        self.read_regs(Register::TypeCMonitoringStatus0, &mut buf)?;
+        Ok(VbusBand::from_registers(buf[0], buf[1]))
+    }
+
+    /// Get the VBUS discharge times `(to_0v_ms, transition_ms)` from
+    /// `VBUS_DISCHARGE_TIME_CTRL`. The high nibble counts steps of 84ms for
+    /// the discharge to 0V, the low nibble steps of 24ms for the discharge
+    /// to a lower PDO voltage.
+    pub fn get_vbus_discharge_times(&mut self) -> Stusb4500Result<(u16, u16), E> {
+        let reg = self.read(Register::VbusDischargeTimeCtrl)? as u16;
+        Ok(((reg >> 4) * 84, (reg & 0x0F) * 24))
+    }
+
+    /// Set the VBUS discharge times, rounded down to the 84ms/24ms steps.
+    /// At most 1260ms to 0V and 360ms for transitions.
+    pub fn set_vbus_discharge_times(&mut self, to_0v_ms: u16, transition_ms: u16) -> Stusb4500Result<(), E> {
+        if to_0v_ms > 15 * 84 || transition_ms > 15 * 24 {
+            return Err(Error::OutaRangeValue);
+        }
+        self.write(Register::VbusDischargeTimeCtrl, (((to_0v_ms / 84) << 4) | (transition_ms / 24)) as u8)
+    }
+
+    /// Get the port status from `PORT_STATUS_1`
+    pub fn get_port_status(&mut self) -> Stusb4500Result<PortStatus, E> {
+        self.read_into(Register::PortStatus1)
+    }
+
+    /// Get `PORT_STATUS_0` and `PORT_STATUS_1` as flags.
+    /// Reading `PORT_STATUS_0` clears its transition bits, like `get_attach_transition`.
+    pub fn get_port_status_flags(&mut self) -> Stusb4500Result<PortStatusFlags, E> {
+        self.read_into(Register::PortStatus0)
+    }
+
+    /// Get the power role of the port, anything but `Sink` while attached
+    /// means the chip is misbehaving
+    pub fn operating_mode(&mut self) -> Stusb4500Result<OperatingMode, E> {
+        Ok(self.get_port_status()?.operating_mode())
+    }
+
+    /// Check if the attached device is a powered cable without a sink at the
+    /// other end (`PORT_STATUS_1` device type 0b101)
+    pub fn is_powered_cable(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_port_status()?.attached_device() == AttachedDevice::PoweredCable)
+    }
+
+    /// Check if a source is attached
+    pub fn is_attached(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.get_port_status()?.attached())
+    }
+
+    /// Check if the attach state changed since the last read.
+    /// `ATTACH_TRANS` in `PORT_STATUS_0` is cleared by reading the register, so
+    /// this reports edges while `is_attached` reports the level. Reading the
+    /// register also clears its other transition bits.
+    pub fn get_attach_transition(&mut self) -> Stusb4500Result<bool, E> {
+        Ok(self.read(Register::PortStatus0)? & 0x01 != 0)
+    }
+
+    /// Check if the RX buffer holds a Source_Capabilities message, i.e. if
+    /// `get_source_capabilities` would return the source's PDOs
+    pub fn has_source_capabilities(&mut self) -> Stusb4500Result<bool, E> {
+        let header: MessageHeader = self.read_into(Register::RXHeaderL)?;
+        Ok(header.is_source_capabilities())
+    }
+
+    /// Get the number of data objects of the last received message from the
+    /// RX header
+    pub fn get_rx_object_count(&mut self) -> Stusb4500Result<u8, E> {
+        let header: MessageHeader = self.read_into(Register::RXHeaderL)?;
+        Ok(header.num_data_objects())
+    }
+
+    /// Get the PD spec revision (1-3) of the last message received from the
+    /// source, which is the revision in use for the contract. `None` if the
+    /// header holds the reserved revision value.
+    pub fn negotiated_pd_revision(&mut self) -> Stusb4500Result<Option<u8>, E> {
+        let header: MessageHeader = self.read_into(Register::RXHeaderL)?;
+        Ok(header.spec_revision())
+    }
+
+    /// Get the source capabilities from the RX buffer.
+    /// The buffer holds the last received message, so this is empty if a later
+    /// message has replaced the Source_Capabilities. Decoding stops at the first
+    /// augmented PDO, which the spec requires to come after all the others.
+    pub fn get_source_capabilities(&mut self) -> Stusb4500Result<heapless::Vec<Pdo, 7>, E> {
+        let mut caps = heapless::Vec::new();
+        let header: MessageHeader = self.read_into(Register::RXHeaderL)?;
+        if !header.is_source_capabilities() {
+            return Ok(caps);
+        }
+        let count = header.num_data_objects() as usize;
+        let mut objects = [0x00; 7 * 4];
+        let objects = &mut objects[..count * 4];
+        self.read_regs(Register::RXDataObj, objects)?;
+        for object in objects.chunks(4) {
+            match Pdo::from_bits(LittleEndian::read_u32(object)) {
+                // Cannot overflow, at most 7 objects are read
+                Some(pdo) => { let _ = caps.push(pdo); },
+                None => break,
+            }
+        }
+        Ok(caps)
+    }
+
+    /// Get the sink capabilities advertised to the source.
+    /// The chip does not keep the sent Sink_Capabilities message, so these are
+    /// the active live sink PDOs it builds the message from.
+    pub fn get_advertised_sink_capabilities(&mut self) -> Stusb4500Result<heapless::Vec<Pdo, 3>, E> {
+        let mut caps = heapless::Vec::new();
+        let num = self.get_live_num_pdo()? as usize;
+        for ch in PdoChannel::ALL.iter().take(num) {
+            // Cannot overflow, at most 3 PDOs are read
+            let _ = caps.push(self.get_pdo(*ch)?);
+        }
+        Ok(caps)
+    }
+
+    /// Get the negotiated contract from the RDO and the source capabilities
+    pub fn get_contract(&mut self) -> Stusb4500Result<Contract, E> {
+        let rdo = self.get_current_rdo()?;
+        let caps = self.get_source_capabilities()?;
+        let voltage_mv = match rdo.source_pdo(&caps) {
+            Some(Pdo::Fixed(pdo)) => Some(pdo.voltage() * 50),
+            _ => None,
+        };
+        Ok(Contract { rdo, voltage_mv })
+    }
+
+    /// Get the contract if it differs from the one seen by the previous call.
+    /// The first call always returns the current contract.
+    pub fn contract_changed(&mut self) -> Stusb4500Result<Option<Contract>, E> {
+        let contract = self.get_contract()?;
+        if self.last_contract == Some(contract) {
+            return Ok(None);
+        }
+        self.last_contract = Some(contract);
+        Ok(Some(contract))
+    }
+
+    /// Wait up to `timeout_ms` for a source to attach and the PD contract to
+    /// be negotiated, polling every millisecond, then read the contract.
+    /// Times out on Type-C only sources, which never give an explicit contract.
+    pub fn wait_for_attach<D: DelayMs<u16>>(&mut self, delay: &mut D, timeout_ms: u16) -> Stusb4500Result<Contract, E> {
+        for elapsed in 0..=timeout_ms {
+            if elapsed > 0 {
+                delay.delay_ms(1);
+            }
+            if self.is_attached()? && self.has_explicit_contract()? {
+                return self.get_contract();
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Get the voltage in mV the chip requested from the source.
+    /// The STUSB4500 has no VBUS selection register, so this is the voltage of
+    /// the source PDO selected by the RDO, `None` if the source capabilities
+    /// are no longer in the RX buffer or the PDO is not fixed.
+    pub fn get_requested_vbus_mv(&mut self) -> Stusb4500Result<Option<u32>, E> {
+        Ok(self.get_contract()?.voltage_mv)
+    }
+
+    /// Read the attach, Type-C, VBUS and contract state in one go
+    pub fn snapshot(&mut self) -> Stusb4500Result<DeviceSnapshot, E> {
+        let attached = self.is_attached()?;
+        let typec = self.get_typec_status()?;
+        let monitoring = self.get_monitoring_status()?;
+        let contract = if self.has_explicit_contract()? {
+            Some(self.get_contract()?)
+        } else {
+            None
+        };
+        Ok(DeviceSnapshot { attached, typec, monitoring, contract })
+    }
+
+    /// Poll `snapshot()` every `interval` and call `callback` whenever it
+    /// differs from the previous one, starting with the first snapshot.
+    /// Runs until the callback returns false or a read fails.
+    #[cfg(feature = "std")]
+    pub fn monitor_changes<F>(&mut self, interval: std::time::Duration, mut callback: F) -> Stusb4500Result<(), E>
+        where F: FnMut(&DeviceSnapshot) -> bool {
+        let mut last: Option<DeviceSnapshot> = None;
+        loop {
+            let snapshot = self.snapshot()?;
+            if last != Some(snapshot) {
+                if !callback(&snapshot) {
+                    return Ok(());
+                }
+                last = Some(snapshot);
+            }
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// Read any register by its raw address.
+    /// Advanced and unchecked: no validation of the address is done.
+    pub fn read_register(&mut self, reg: u8) -> Stusb4500Result<u8, E> {
+        let mut buf = [0x00; 1];
+        self.i2c.write(self.address, &[reg]).map_i2c()?;
+        self.i2c.read(self.address, &mut buf).map_i2c()?;
+        Ok(buf[0])
+    }
+
+    /// Write any register by its raw address.
+    /// Advanced and unchecked: writing reserved registers may put the chip in
+    /// an undocumented state.
+    pub fn write_register(&mut self, reg: u8, val: u8) -> Stusb4500Result<(), E> {
+        self.i2c.write(self.address, &[reg, val]).map_i2c()
+    }
+
+    /// Poll `ready` until it returns true or the attempts are exhausted
+    pub(crate) fn poll_until<D, F>(&mut self, delay: &mut D, config: Option<PollConfig>, mut ready: F) -> Stusb4500Result<(), E>
+        where D: DelayUs<u32>, F: FnMut(&mut Self) -> Stusb4500Result<bool, E> {
+        let config = config.unwrap_or(self.poll);
+        for attempt in 0..config.max_attempts {
+            if attempt > 0 {
+                if let Some(us) = config.interval_us {
+                    delay.delay_us(us);
+                }
+            }
+            if ready(self)? {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    // *****************************************************************
+    // NVM access functions
+
+    /// Wait for the NVM controller to finish the pending request, polling with
+    /// the driver's poll timing
+    fn nvm_wait<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<(), E> {
+        self.poll_until(delay, None, |dev| {
+            let ctrl = dev.read(Register::FTPCtrl0).map_err(|err| match err {
+                Error::I2CError(err) => Error::BusTimeout(err),
+                err => err,
+            })?;
+            Ok(ctrl & FTP_CUST_REQ == 0)
+        })
+    }
+
+    /// Wait until no PD negotiation is in progress, i.e. the policy engine is
+    /// detached, waiting for capabilities or holding a contract
+    fn ensure_idle<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<(), E> {
+        self.poll_until(delay, None, |dev| {
+            Ok(matches!(dev.get_pe_state()?,
+                PolicyEngineState::Init
+                | PolicyEngineState::SnkWaitForCapabilities
+                | PolicyEngineState::SnkReady))
+        })
+    }
+
+    /// Erase and program the whole NVM
+    fn nvm_program<D: DelayUs<u32>>(&mut self, bytes: &[u8; NVM_SIZE], delay: &mut D) -> Stusb4500Result<(), E> {
+        self.nvm_program_sectors(bytes, NVM_ALL_SECTORS, delay)
+    }
+
+    /// Erase and program the sectors selected by the bit mask `sectors`
+    fn nvm_program_sectors<D: DelayUs<u32>>(&mut self, bytes: &[u8; NVM_SIZE], sectors: u8, delay: &mut D) -> Stusb4500Result<(), E> {
+        trace!("write_nvm: sectors {:#04x} {:02x?}", sectors, bytes);
+        self.ensure_idle(delay)?;
+        self.unlock_nvm()?;
+        let result = self.nvm_erase(sectors, delay).and_then(|_| {
+            for (sector, data) in bytes.chunks(NVM_SECTOR_SIZE).enumerate() {
+                if sectors & (1 << sector) != 0 {
+                    self.nvm_write_sector(sector as u8, data, delay)?;
+                }
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            // Best effort to leave the NVM locked
+            let _ = self.lock_nvm();
+            return result;
+        }
+        self.lock_nvm()?;
+        trace!("write_nvm: done");
+        Ok(())
+    }
+
+    /// Read one sector, the NVM must be unlocked
+    fn nvm_read_sector<D: DelayUs<u32>>(&mut self, sector: u8, buf: &mut [u8], delay: &mut D) -> Stusb4500Result<(), E> {
+        debug_assert_eq!(buf.len(), NVM_SECTOR_SIZE);
+        self.write(Register::FTPCtrl0, FTP_CUST_PWR | FTP_CUST_RST_N)?;
+        self.write(Register::FTPCtrl1, NVM_OP_READ & FTP_CUST_OPCODE)?;
+        self.write(Register::FTPCtrl0, (sector & FTP_CUST_SECT) | FTP_CUST_PWR | FTP_CUST_RST_N | FTP_CUST_REQ)?;
+        self.nvm_wait(delay)?;
+        self.read_regs(Register::RWBuffer, buf)
+    }
+
+    /// Issue a NVM controller request with `opcode`, the NVM must be unlocked
+    fn nvm_request<D: DelayUs<u32>>(&mut self, opcode: u8, sector: u8, delay: &mut D) -> Stusb4500Result<(), E> {
+        self.write(Register::FTPCtrl1, opcode)?;
+        self.write(Register::FTPCtrl0, (sector & FTP_CUST_SECT) | FTP_CUST_PWR | FTP_CUST_RST_N | FTP_CUST_REQ)?;
+        self.nvm_wait(delay)
+    }
+
+    /// Erase the sectors selected by the bit mask `sectors`, the NVM must be unlocked
+    fn nvm_erase<D: DelayUs<u32>>(&mut self, sectors: u8, delay: &mut D) -> Stusb4500Result<(), E> {
+        // Must be cleared for partial erase
+        self.write(Register::RWBuffer, 0x00)?;
+        self.nvm_request(((sectors << 3) & FTP_CUST_SER) | (NVM_OP_WRITE_SER & FTP_CUST_OPCODE), 0, delay)?;
+        self.nvm_request(NVM_OP_SOFT_PROG_SECTOR & FTP_CUST_OPCODE, 0, delay)?;
+        self.nvm_request(NVM_OP_ERASE_SECTOR & FTP_CUST_OPCODE, 0, delay)
+    }
+
+    /// Program one erased sector, the NVM must be unlocked
+    fn nvm_write_sector<D: DelayUs<u32>>(&mut self, sector: u8, data: &[u8], delay: &mut D) -> Stusb4500Result<(), E> {
+        debug_assert_eq!(data.len(), NVM_SECTOR_SIZE);
+        self.write_regs(Register::RWBuffer, data)?;
+        self.write(Register::FTPCtrl0, FTP_CUST_PWR | FTP_CUST_RST_N)?;
+        self.nvm_request(NVM_OP_WRITE_PL & FTP_CUST_OPCODE, 0, delay)?;
+        self.nvm_request(NVM_OP_PROG_SECTOR & FTP_CUST_OPCODE, sector, delay)
+    }
+
+    /// Read all sectors, the NVM must be unlocked
+    fn nvm_read_all<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u8; NVM_SIZE], E> {
+        let mut bytes = [0x00; NVM_SIZE];
+        for (sector, buf) in bytes.chunks_mut(NVM_SECTOR_SIZE).enumerate() {
+            self.nvm_read_sector(sector as u8, buf, delay)?;
+        }
+        Ok(bytes)
+    }
+
+    // *****************************************************************
+    // Raw access functions
+
+    /// Write a byte register
+    pub(crate) fn write(&mut self, register: Register, value: u8) -> Stusb4500Result<(), E> {
+        let buf = [register as u8, value];
+        self.i2c.write(self.address, &buf).map_i2c()
+    }
+
+    /// Write a word register
+    pub(crate) fn write_word(&mut self, register: Register, word: u32) -> Stusb4500Result<(), E> {
+        let mut buf = [0x00; 4];
+        LittleEndian::write_u32(&mut buf, word);
+        self.write_regs(register, &buf)
+    }
+
+    /// Write up to 8 contiguous registers starting at `start` in a single
+    /// transaction, `Error::OutaRangeValue` for more
+    pub(crate) fn write_regs(&mut self, start: Register, bytes: &[u8]) -> Stusb4500Result<(), E> {
+        if bytes.len() > 8 {
+            return Err(Error::OutaRangeValue);
+        }
+        let mut buf = [0x00; 9];
+        buf[0] = start as u8;
+        buf[1..=bytes.len()].copy_from_slice(bytes);
+        self.i2c.write(self.address, &buf[..=bytes.len()]).map_i2c()
+    }
+
+    /// Read a byte register
+    pub(crate) fn read(&mut self, register: Register) -> Stusb4500Result<u8, E> {
+        let mut buf = [0x00; 1];
+        self.read_regs(register, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Read a word register
+    pub(crate) fn read_word(&mut self, register: Register) -> Stusb4500Result<u32, E> {
+        let mut buf = [0x00; 4];
+        self.read_regs(register, &mut buf)?;
+        Ok(LittleEndian::read_u32(&buf))
+    }
+
+    /// Read and decode the block of registers starting at `start`
+    pub(crate) fn read_into<T: FromRegisters>(&mut self, start: Register) -> Stusb4500Result<T, E> {
+        let mut buf = [0x00; 8];
+        self.read_regs(start, &mut buf[..T::LEN])?;
+        Ok(T::from_registers(&buf[..T::LEN]))
+    }
+
+    /// Read contiguous registers starting at `start`.
+    /// The embedded-hal 0.2 I2C traits do not report the number of bytes
+    /// transferred, so `buf` is assumed to be completely filled on success.
+    pub(crate) fn read_regs(&mut self, start: Register, buf: &mut [u8]) -> Stusb4500Result<(), E> {
+        self.i2c.write(self.address, &[start as u8]).map_i2c()?;
+        self.i2c.read(self.address, buf).map_i2c()
+    }
+
+}
+
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+    use embedded_hal_mock::delay::MockNoop;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use crate::*;
+
+    const ADDR: u8 = STUSB4500_ADDR;
+
+    /// Output pin remembering its last state, failing every write if `broken`
+    #[derive(Default)]
+    struct TestPin {
+        high: Option<bool>,
+        broken: bool,
+    }
+
+    impl hal::digital::v2::OutputPin for TestPin {
+        type Error = ();
+        fn set_low(&mut self) -> Result<(), ()> {
+            if self.broken {
+                return Err(());
+            }
+            self.high = Some(false);
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), ()> {
+            if self.broken {
+                return Err(());
+            }
+            self.high = Some(true);
+            Ok(())
+        }
+    }
+
+    /// Transactions of `negotiate` with a single 5V/3A PDO, up to the VBUS
+    /// ready poll. The policy engine is back in `SnkReady` on the second poll.
+    fn negotiate_5v_transactions() -> Vec<Transaction> {
+        let mut t = vec![
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO1 as u8, 0x2C, 0x91, 0x01, 0x00]),
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 1]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        t.extend(pe_state_transactions(0x14));
+        t.extend(pe_state_transactions(0x18));
+        t
+    }
+
+    fn nvm_enter_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::FTPCustPassword as u8, 0x47]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0x00]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xC0]),
+        ]
+    }
+
+    fn nvm_exit_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0x40, 0x00]),
+            Transaction::write(ADDR, vec![Register::FTPCustPassword as u8, 0x00]),
+        ]
+    }
+
+    fn nvm_read_sector_transactions(sector: u8, data: &[u8]) -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xC0]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl1 as u8, 0x00]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xD0 | sector]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8]),
+            Transaction::read(ADDR, vec![0xC0 | sector]),
+            Transaction::write(ADDR, vec![Register::RWBuffer as u8]),
+            Transaction::read(ADDR, data.to_vec()),
+        ]
+    }
+
+    /// Transactions reading all sectors of an unlocked NVM returning `nvm`
+    fn nvm_read_all_transactions(nvm: &[u8; NVM_SIZE]) -> Vec<Transaction> {
+        let mut t = Vec::new();
+        for (sector, data) in nvm.chunks(NVM_SECTOR_SIZE).enumerate() {
+            t.extend(nvm_read_sector_transactions(sector as u8, data));
+        }
+        t
+    }
+
+    /// Transactions of a NVM controller request that completes on the first poll
+    fn nvm_request_transactions(opcode: u8, sector: u8) -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::FTPCtrl1 as u8, opcode]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xD0 | sector]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8]),
+            Transaction::read(ADDR, vec![0xC0 | sector]),
+        ]
+    }
+
+    /// Transactions of a `PE_FSM` read returning `state`
+    fn pe_state_transactions(state: u8) -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![state]),
+        ]
+    }
+
+    /// Transactions of a `DPM_PDO_NUMB` read returning `num` followed by reads
+    /// of the live sink PDO registers returning `pdos`, starting at PDO1
+    fn live_pdo_transactions(num: u8, pdos: &[Pdo]) -> Vec<Transaction> {
+        let mut t = vec![
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![num]),
+        ];
+        let regs = [Register::DPMSNKPDO1, Register::DPMSNKPDO2, Register::DPMSNKPDO3];
+        for (reg, pdo) in regs.iter().zip(pdos) {
+            t.push(Transaction::write(ADDR, vec![*reg as u8]));
+            t.push(Transaction::read(ADDR, pdo.bits().to_le_bytes().to_vec()));
+        }
+        t
+    }
+
+    /// Transactions of a complete NVM erase and program of `nvm`
+    fn nvm_write_transactions(nvm: &[u8; NVM_SIZE]) -> Vec<Transaction> {
+        nvm_write_sectors_transactions(nvm, 0x1F)
+    }
+
+    /// Transactions of a NVM erase and program of the sectors in `mask`
+    fn nvm_write_sectors_transactions(nvm: &[u8; NVM_SIZE], mask: u8) -> Vec<Transaction> {
+        let mut t = pe_state_transactions(0x18);
+        t.extend(nvm_enter_transactions());
+        t.push(Transaction::write(ADDR, vec![Register::RWBuffer as u8, 0x00]));
+        t.extend(nvm_request_transactions(mask << 3 | 0x02, 0));
+        t.extend(nvm_request_transactions(0x07, 0));
+        t.extend(nvm_request_transactions(0x05, 0));
+        for (sector, data) in nvm.chunks(NVM_SECTOR_SIZE).enumerate() {
+            if mask & (1 << sector) == 0 {
+                continue;
+            }
+            let mut buffer = vec![Register::RWBuffer as u8];
+            buffer.extend_from_slice(data);
+            t.push(Transaction::write(ADDR, buffer));
+            t.push(Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xC0]));
+            t.extend(nvm_request_transactions(0x01, 0));
+            t.extend(nvm_request_transactions(0x06, sector as u8));
+        }
+        t.extend(nvm_exit_transactions());
+        t
+    }
+
+    /// Transactions of a complete NVM read returning `nvm`
+    fn nvm_read_transactions(nvm: &[u8; NVM_SIZE]) -> Vec<Transaction> {
+        let mut t = nvm_enter_transactions();
+        t.extend(nvm_read_all_transactions(nvm));
+        t.extend(nvm_exit_transactions());
+        t
+    }
+
+    #[test]
+    fn it_works() {
+        assert_eq!(2 + 2, 4);
+    }
+
+    #[test]
+    fn effective_current_mismatch() {
+        // Operating current 1A (100 * 10mA), max 3A, capability mismatch set
+        let rdo: u32 = 1 << 28 | 1 << 26 | 100 << 10 | 300;
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.effective_current_ma().unwrap(), (1000, true));
+        i2c.done();
+    }
+
+    #[test]
+    fn wait_for_vbus_ready_after_polls() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![MonitoringStatus::VbusReady.bits()]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.wait_for_vbus_ready(&mut MockNoop::new(), None).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn wait_for_vbus_ready_respects_attempts() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let config = PollConfig { max_attempts: 3, interval_us: None };
+        match stusb.wait_for_vbus_ready(&mut MockNoop::new(), Some(config)) {
+            Err(Error::Timeout) => {},
+            _ => panic!("expected timeout"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn explicit_contract() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x18]),
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x14]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.has_explicit_contract().unwrap());
+        assert!(!stusb.has_explicit_contract().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn write_regs_single_transaction() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO2 as u8, 0x2C, 0xB1, 0x04, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_pdo(PdoChannel::PDO2, &Pdo::new_fixed(300, 300)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn enable_disable_alerts_modify_only_given_bits() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8]),
+            Transaction::read(ADDR, vec![0b1111_0011]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8, 0b1011_0011]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8]),
+            Transaction::read(ADDR, vec![0b1000_0001]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8, 0b1001_0011]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.enable_alerts(AlertMask::PortStatus).unwrap();
+        stusb.disable_alerts(AlertMask::CCFaultStatus | AlertMask::PRTStatus).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn map_i2c_allows_question_mark() {
+        use hal::blocking::i2c::Write;
+
+        fn user_write<I2C: Write>(i2c: &mut I2C) -> Result<(), Error<I2C::Error>> {
+            i2c.write(ADDR, &[Register::ResetCtrl as u8, 0x00]).map_i2c()?;
+            Ok(())
+        }
+
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        user_write(&mut i2c).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn hard_reset_received() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PRTStatus as u8]),
+            Transaction::read(ADDR, vec![0x01]),
+            Transaction::write(ADDR, vec![Register::PRTStatus as u8]),
+            Transaction::read(ADDR, vec![0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.hard_reset_in_progress().unwrap());
+        assert!(!stusb.hard_reset_in_progress().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn with_active_pdos_only_writes_count() {
+        // Any access to the PDO2/PDO3 registers would fail the mock
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 1]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.with_active_pdos(1).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn self_test_good_and_bad_id() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x25]),
+            Transaction::write(ADDR, vec![Register::BcdTypeCRevL as u8]),
+            Transaction::read(ADDR, vec![0x20, 0x01]),
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0xFF]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.self_test().unwrap();
+        match stusb.self_test() {
+            Err(Error::SelfTestFailed) => {},
+            _ => panic!("expected self test failure"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn device_id_short_read() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.get_device_id(), Err(Error::ShortRead)));
+        i2c.done();
+    }
+
+    #[test]
+    fn live_and_nvm_num_pdo() {
+        let mut expectations = vec![
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![0x02]),
+        ];
+        expectations.extend(nvm_read_transactions(&nvm::tests::FACTORY_NVM));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_live_num_pdo().unwrap(), 2);
+        assert_eq!(stusb.get_nvm_num_pdo(&mut MockNoop::new()).unwrap(), 3);
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_sectors_little_endian() {
+        let expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let sectors = stusb.get_nvm(&mut MockNoop::new()).unwrap();
+        assert_eq!(sectors[0], 0x0000_4500_AAB0_0000);
+        assert_eq!(sectors[4], 0xFB40_0043_2190_4B00);
+        i2c.done();
+    }
+
+    #[test]
+    fn batched_nvm_reads_unlock_once() {
+        let mut expectations = nvm_enter_transactions();
+        expectations.extend(nvm_read_all_transactions(&nvm::tests::FACTORY_NVM));
+        expectations.extend(nvm_read_all_transactions(&nvm::tests::FACTORY_NVM));
+        expectations.extend(nvm_exit_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.unlock_nvm().unwrap();
+        let first = stusb.get_nvm_raw(&mut MockNoop::new()).unwrap();
+        let second = stusb.get_nvm_raw(&mut MockNoop::new()).unwrap();
+        stusb.lock_nvm().unwrap();
+        assert_eq!(first, second);
+        i2c.done();
+    }
+
+    #[test]
+    fn contract_voltage_from_source_capabilities() {
+        let rdo: u32 = 2 << 28 | 200 << 10 | 200;
+        // Source_Capabilities with 5V/3A and 9V/2A
+        let header: u16 = 2 << 12 | 0x1;
+        let mut caps = Vec::new();
+        caps.extend_from_slice(&(100u32 << 10 | 300).to_le_bytes());
+        caps.extend_from_slice(&(180u32 << 10 | 200).to_le_bytes());
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, header.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXDataObj as u8]),
+            Transaction::read(ADDR, caps),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let contract = stusb.get_contract().unwrap();
+        assert_eq!(contract.voltage_mv, Some(9000));
+        assert_eq!(contract.operating_current_ma(), 2000);
+        i2c.done();
+    }
+
+    #[test]
+    fn set_vbus_alarm_thresholds_round_trip() {
+        let mut expected = nvm::tests::FACTORY_NVM;
+        expected[0x1C] = 0x25;
+        expected[0x1D] = 0x37;
+        let mut expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        expectations.extend(nvm_write_transactions(&expected));
+        expectations.extend(nvm_read_transactions(&expected));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_vbus_alarm_thresholds(PdoChannel::PDO2, 7, 12, &mut MockNoop::new()).unwrap();
+        let config = NvmConfig::from_bytes(stusb.get_nvm_bytes(&mut MockNoop::new()).unwrap());
+        assert_eq!(config.voltage_lower_limit(PdoChannel::PDO2), Some(7));
+        assert_eq!(config.voltage_upper_limit(PdoChannel::PDO2), 12);
+        i2c.done();
+    }
+
+    #[test]
+    fn set_vbus_alarm_thresholds_out_of_range() {
+        let expectations = [];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        match stusb.set_vbus_alarm_thresholds(PdoChannel::PDO2, 4, 12, &mut MockNoop::new()) {
+            Err(Error::OutaRangeValue) => {},
+            _ => panic!("expected out of range error"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn handle_interrupt_reads_only_flagged() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![Alert::PortStatus.bits()]),
+            Transaction::write(ADDR, vec![Register::PortStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x01, 0x01]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let snapshot = stusb.handle_interrupt().unwrap();
+        assert_eq!(snapshot.alert, Alert::PortStatus);
+        assert_eq!(snapshot.port_status, Some([0x01, 0x01]));
+        assert_eq!(snapshot.monitoring_status, None);
+        assert_eq!(snapshot.prt_status, None);
+        i2c.done();
+    }
+
+    #[test]
+    fn two_devices_on_shared_bus() {
+        let expectations = [
+            Transaction::write(0x28, vec![Register::DeviceId as u8]),
+            Transaction::read(0x28, vec![0x25]),
+            Transaction::write(0x29, vec![Register::DeviceId as u8]),
+            Transaction::read(0x29, vec![0x21]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let bus = shared_bus::BusManagerSimple::new(i2c.clone());
+        let mut first = STUSB4500::new(bus.acquire_i2c(), Address::Default);
+        let mut second = STUSB4500::new(bus.acquire_i2c(), Address::Strap(false, true));
+        assert_eq!(first.get_device_id().unwrap(), 0x25);
+        assert_eq!(second.get_device_id().unwrap(), 0x21);
+        i2c.done();
+    }
+
+    #[test]
+    fn fault_flags_combinations() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::CCHWFaultStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x40]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::CCHWFaultStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x90]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x20]),
+            Transaction::write(ADDR, vec![Register::CCHWFaultStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus0 as u8]),
+            // Transition latches alone are not faults
+            Transaction::read(ADDR, vec![0x16]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        // VPU_VALID alone is not a fault
+        assert!(stusb.get_faults().unwrap().is_empty());
+        assert_eq!(stusb.get_faults().unwrap(),
+            FaultFlags::VbusDischargeFault | FaultFlags::CCOverVoltage | FaultFlags::VbusOverVoltage);
+        assert_eq!(stusb.get_faults().unwrap(), FaultFlags::VbusUnderVoltage);
+        i2c.done();
+    }
+
+    #[test]
+    fn request_pdo2() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 2]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.request_pdo(PdoChannel::PDO2).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_wire_bytes_match_st_export() {
+        let expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let bytes = stusb.get_nvm_wire_bytes(&mut MockNoop::new()).unwrap();
+        // Sector 0 and 4 of the factory configuration as exported by the ST GUI
+        assert_eq!(bytes[..8], [0x00, 0x00, 0xB0, 0xAA, 0x00, 0x45, 0x00, 0x00]);
+        assert_eq!(bytes[32..], [0x00, 0x4B, 0x90, 0x21, 0x43, 0x00, 0x40, 0xFB]);
+        i2c.done();
+
+        let expectations = nvm_write_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_nvm_wire_bytes(&bytes, &mut MockNoop::new()).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn read_into_status_registers() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x0B]),
+            Transaction::write(ADDR, vec![Register::TypeCStatus as u8]),
+            Transaction::read(ADDR, vec![0x82]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let cc = stusb.get_cc_status().unwrap();
+        assert_eq!((cc.cc1_state(), cc.cc2_state()), (3, 2));
+        let typec = stusb.get_typec_status().unwrap();
+        assert_eq!(typec.fsm_state(), TypeCFsmState::AttachedSnk);
+        assert!(typec.reverse());
+        i2c.done();
+    }
+
+    #[test]
+    fn usb_comm_capable_flag() {
+        let set: u32 = 1 << 28 | 1 << 25 | 100 << 10 | 100;
+        let clear: u32 = 1 << 28 | 100 << 10 | 100;
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, set.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, clear.to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.usb_comm_capable().unwrap());
+        assert!(!stusb.usb_comm_capable().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn attach_transition_set() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PortStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x01]),
+            Transaction::write(ADDR, vec![Register::PortStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.get_attach_transition().unwrap());
+        // Cleared by the first read
+        assert!(!stusb.get_attach_transition().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn max_configured_power_three_pdos() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![3]),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO1 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(100, 300).bits().to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO2 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(300, 300).bits().to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO3 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(180, 200).bits().to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.max_configured_power_mw().unwrap(), 45_000);
+        i2c.done();
+    }
+
+    #[test]
+    fn not_fixed_and_invalid_pdo() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO2 as u8]),
+            Transaction::read(ADDR, 0xC000_0000u32.to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let variable = Pdo::from_bits(0x4000_0000 | 180 << 20 | 100 << 10 | 300).unwrap();
+        assert!(matches!(stusb.set_pdo(PdoChannel::PDO2, &variable), Err(Error::NotFixedPdo)));
+        assert!(matches!(stusb.get_pdo(PdoChannel::PDO2), Err(Error::InvalidPdo)));
+        i2c.done();
+    }
+
+    #[test]
+    fn set_pdo1_rejects_non_5v() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO1 as u8, 0x2C, 0x91, 0x01, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        match stusb.set_pdo(PdoChannel::PDO1, &Pdo::new_fixed(180, 300)) {
+            Err(Error::InvalidPdo1Voltage) => {},
+            _ => panic!("expected PDO1 voltage error"),
+        }
+        stusb.set_pdo(PdoChannel::PDO1, &Pdo::new_fixed(100, 300)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn has_source_capabilities_states() {
+        // Nothing received yet, then an Accept control message, then 3 source PDOs
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x00, 0x00]),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x43, 0x01]),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0xA1, 0x31]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(!stusb.has_source_capabilities().unwrap());
+        assert!(!stusb.has_source_capabilities().unwrap());
+        assert!(stusb.has_source_capabilities().unwrap());
+        i2c.done();
+    }
+
+    /// Bus where only the device at `present` acknowledges
+    struct ProbeBus {
+        present: u8,
+    }
+
+    impl hal::blocking::i2c::Write for ProbeBus {
+        type Error = ();
+        fn write(&mut self, address: u8, _bytes: &[u8]) -> Result<(), ()> {
+            if address == self.present { Ok(()) } else { Err(()) }
+        }
+    }
+
+    impl hal::blocking::i2c::Read for ProbeBus {
+        type Error = ();
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), ()> {
+            if address != self.present {
+                return Err(());
+            }
+            buffer[0] = 0x25;
+            Ok(())
+        }
+    }
+
+    /// Bus accepting writes but failing all reads, like a master timing out
+    /// on a stretched clock
+    struct StallBus;
+
+    impl hal::blocking::i2c::Write for StallBus {
+        type Error = ();
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    impl hal::blocking::i2c::Read for StallBus {
+        type Error = ();
+        fn read(&mut self, _address: u8, _buffer: &mut [u8]) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn nvm_wait_bus_timeout() {
+        let mut stusb = STUSB4500::new(StallBus, Address::Default);
+        assert!(matches!(stusb.get_nvm_raw(&mut MockNoop::new()), Err(Error::BusTimeout(()))));
+        // Other reads still report plain bus errors
+        assert!(matches!(stusb.get_device_id(), Err(Error::I2CError(()))));
+    }
+
+    #[test]
+    fn probe_address_finds_strapped() {
+        let candidates = [
+            Address::Default,
+            Address::Strap(false, true),
+            Address::Strap(true, false),
+        ];
+        let mut bus = ProbeBus { present: 0x29 };
+        assert_eq!(STUSB4500::probe_address(&mut bus, &candidates).unwrap(), Address::Strap(false, true));
+        let mut bus = ProbeBus { present: 0x2B };
+        match STUSB4500::probe_address(&mut bus, &candidates) {
+            Err(Error::DeviceNotFound) => {},
+            _ => panic!("expected device not found"),
+        }
+    }
+
+    #[test]
+    fn vbus_discharge_times() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::VbusDischargeTimeCtrl as u8]),
+            Transaction::read(ADDR, vec![0x9C]),
+            Transaction::write(ADDR, vec![Register::VbusDischargeTimeCtrl as u8, 0x9C]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_vbus_discharge_times().unwrap(), (756, 288));
+        stusb.set_vbus_discharge_times(756, 300).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn negotiate_and_enable_success() {
+        let rdo: u32 = 1 << 28 | 300 << 10 | 300;
+        let mut expectations = negotiate_5v_transactions();
+        expectations.extend(vec![
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![MonitoringStatus::VbusReady.bits()]),
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x00, 0x00]),
+        ]);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let mut pin = TestPin::default();
+        let contract = stusb.negotiate_and_enable(&[Pdo::new_fixed(100, 300)], &mut pin, &mut MockNoop::new()).unwrap();
+        assert_eq!(contract.operating_current_ma(), 3000);
+        assert_eq!(pin.high, Some(true));
+        i2c.done();
+    }
+
+    #[test]
+    fn negotiate_waits_for_contract_with_stale_vbus_ready() {
+        let rdo: u32 = 1 << 28 | 300 << 10 | 300;
+        let mut expectations = vec![
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO1 as u8, 0x2C, 0x91, 0x01, 0x00]),
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 1]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        // VBUS_READY stays set from the old contract, only the policy engine
+        // tells when the new one is in place
+        for state in [0x14, 0x16, 0x18].iter() {
+            expectations.extend(pe_state_transactions(*state));
+        }
+        expectations.extend(vec![
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![MonitoringStatus::VbusReady.bits()]),
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x00, 0x00]),
+        ]);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let contract = stusb.negotiate(&[Pdo::new_fixed(100, 300)], &mut MockNoop::new()).unwrap();
+        assert_eq!(contract.operating_current_ma(), 3000);
+        i2c.done();
+    }
+
+    #[test]
+    fn negotiate_and_enable_vbus_timeout() {
+        let mut expectations = Vec::new();
+        for _ in 0..2 {
+            expectations.extend(negotiate_5v_transactions());
+            expectations.extend(vec![
+                Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+                Transaction::read(ADDR, vec![0x00]),
+                Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+                Transaction::read(ADDR, vec![0x00]),
+            ]);
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_poll_config(PollConfig { max_attempts: 2, interval_us: None });
+        let mut pin = TestPin::default();
+        match stusb.negotiate_and_enable(&[Pdo::new_fixed(100, 300)], &mut pin, &mut MockNoop::new()) {
+            Err(Error::Timeout) => {},
+            _ => panic!("expected timeout"),
+        }
+        assert_eq!(pin.high, Some(false));
+        // A failing pin does not hide the negotiation error
+        let mut pin = TestPin { high: None, broken: true };
+        match stusb.negotiate_and_enable(&[Pdo::new_fixed(100, 300)], &mut pin, &mut MockNoop::new()) {
+            Err(Error::Timeout) => {},
+            _ => panic!("expected timeout"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn raw_register_access() {
+        let expectations = [
+            Transaction::write(ADDR, vec![0x2E]),
+            Transaction::read(ADDR, vec![0x5A]),
+            Transaction::write(ADDR, vec![0x24, 0xA5]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.read_register(0x2E).unwrap(), 0x5A);
+        stusb.write_register(0x24, 0xA5).unwrap();
+        i2c.done();
+    }
+
+    /// Transactions of `get_contract` for a fixed source PDO at position 2
+    fn contract_transactions(voltage: u32, current: u32, mismatch: bool) -> Vec<Transaction> {
+        let rdo: u32 = 2 << 28 | (mismatch as u32) << 26 | current << 10 | current;
+        let header: u16 = 2 << 12 | 0x1;
+        let mut caps = Vec::new();
+        caps.extend_from_slice(&(100u32 << 10 | 300).to_le_bytes());
+        caps.extend_from_slice(&(voltage << 10 | current).to_le_bytes());
+        vec![
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, header.to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::RXDataObj as u8]),
+            Transaction::read(ADDR, caps),
+        ]
+    }
+
+    #[test]
+    fn contract_satisfied_and_under_provisioned() {
+        let top_pdo = vec![
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![3]),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO3 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(180, 200).bits().to_le_bytes().to_vec()),
+        ];
+        let mut expectations = contract_transactions(180, 200, false);
+        expectations.extend(top_pdo.clone());
+        expectations.extend(contract_transactions(100, 200, false));
+        expectations.extend(top_pdo);
+        expectations.extend(contract_transactions(180, 200, true));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.contract_satisfied().unwrap());
+        assert!(!stusb.contract_satisfied().unwrap());
+        assert!(!stusb.contract_satisfied().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn wait_for_attach_after_polls() {
+        let poll = |status: u8| vec![
+            Transaction::write(ADDR, vec![Register::PortStatus1 as u8]),
+            Transaction::read(ADDR, vec![status]),
+        ];
+        let mut expectations = Vec::new();
+        for _ in 0..3 {
+            expectations.extend(poll(0x00));
+        }
+        // Attached while still negotiating, then with the contract in place
+        expectations.extend(poll(0x01));
+        expectations.extend(pe_state_transactions(0x16));
+        expectations.extend(poll(0x01));
+        expectations.extend(pe_state_transactions(0x18));
+        expectations.extend(contract_transactions(180, 200, false));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let contract = stusb.wait_for_attach(&mut MockNoop::new(), 10).unwrap();
+        assert_eq!(contract.voltage_mv, Some(9000));
+        i2c.done();
+    }
+
+    #[test]
+    fn wait_for_attach_timeout() {
+        let mut expectations = Vec::new();
+        for _ in 0..3 {
+            expectations.push(Transaction::write(ADDR, vec![Register::PortStatus1 as u8]));
+            expectations.push(Transaction::read(ADDR, vec![0x00]));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.wait_for_attach(&mut MockNoop::new(), 2), Err(Error::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn configure_standard_profile() {
+        let pdo_write = |reg: Register, voltage: u16| {
+            let mut data = vec![reg as u8];
+            data.extend_from_slice(&Pdo::new_fixed(voltage, 300).bits().to_le_bytes());
+            Transaction::write(ADDR, data)
+        };
+        let expectations = [
+            pdo_write(Register::DPMSNKPDO1, 100),
+            pdo_write(Register::DPMSNKPDO2, 180),
+            pdo_write(Register::DPMSNKPDO3, 300),
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 3]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.configure_standard_profile(3000).unwrap();
+        assert!(matches!(stusb.configure_standard_profile(5010), Err(Error::OutaRangeValue)));
+        i2c.done();
+    }
+
+    #[test]
+    fn get_nvm_config_factory() {
+        let expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let config = stusb.get_nvm_config(&mut MockNoop::new()).unwrap();
+        assert_eq!(config.to_bytes(), nvm::tests::FACTORY_NVM);
+        assert_eq!(config.num_pdo(), 3);
+        assert_eq!(config.pdo_voltage(PdoChannel::PDO2), 300);
+        assert_eq!(config.pdo_current(PdoChannel::PDO3), 100);
+        assert_eq!(config.voltage_upper_limit(PdoChannel::PDO1), 15);
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_matches_live() {
+        let live = |pdo3: Pdo| {
+            let mut t = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+            t.extend(live_pdo_transactions(3, &[Pdo::new_fixed(100, 150), Pdo::new_fixed(300, 150), pdo3]));
+            t
+        };
+        let mut expectations = live(Pdo::new_fixed(400, 100));
+        expectations.extend(live(Pdo::new_fixed(180, 300)));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.nvm_matches_live(&mut MockNoop::new()).unwrap());
+        assert!(!stusb.nvm_matches_live(&mut MockNoop::new()).unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn advertised_sink_capabilities() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![2]),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO1 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(100, 300).bits().to_le_bytes().to_vec()),
+            Transaction::write(ADDR, vec![Register::DPMSNKPDO2 as u8]),
+            Transaction::read(ADDR, Pdo::new_fixed(180, 200).bits().to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let caps = stusb.get_advertised_sink_capabilities().unwrap();
+        assert_eq!(caps.as_slice(), &[Pdo::new_fixed(100, 300), Pdo::new_fixed(180, 200)]);
+        i2c.done();
+    }
+
+    #[test]
+    fn pdo_config_report_counts() {
+        let live = |num: u8| {
+            let mut t = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+            t.extend(live_pdo_transactions(num,
+                &[Pdo::new_fixed(100, 150), Pdo::new_fixed(300, 150), Pdo::new_fixed(400, 100)]));
+            t
+        };
+        let mut expectations = live(3);
+        expectations.extend(live(2));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let report = stusb.pdo_config_report(&mut MockNoop::new()).unwrap();
+        assert!(report.consistent);
+        assert_eq!(report.nvm_pdos, report.live_pdos);
+        assert_eq!(report.nvm_pdos[1], Pdo::new_fixed(300, 150));
+        let report = stusb.pdo_config_report(&mut MockNoop::new()).unwrap();
+        assert!(!report.consistent);
+        assert_eq!((report.nvm_num_pdo, report.live_num_pdo), (3, 2));
+        assert_eq!(report.nvm_pdos, report.live_pdos);
+        i2c.done();
+    }
+
+    #[test]
+    fn write_nvm_waits_for_idle() {
+        // Select and transition sink, then ready
+        let mut expectations = pe_state_transactions(0x16);
+        expectations.extend(pe_state_transactions(0x17));
+        expectations.extend(nvm_write_transactions(&nvm::tests::FACTORY_NVM));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.write_nvm_bytes(&nvm::tests::FACTORY_NVM, &mut MockNoop::new()).unwrap();
+        i2c.done();
+
+        let mut expectations = Vec::new();
+        for _ in 0..3 {
+            expectations.extend(pe_state_transactions(0x16));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_poll_config(PollConfig { max_attempts: 3, interval_us: None });
+        assert!(matches!(stusb.write_nvm_bytes(&nvm::tests::FACTORY_NVM, &mut MockNoop::new()), Err(Error::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn port_status_flags() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PortStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x01, 0x41]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let flags = stusb.get_port_status_flags().unwrap();
+        assert_eq!(flags, PortStatusFlags::Attach | PortStatusFlags::AttachTrans);
+        i2c.done();
+    }
+
+    #[test]
+    fn soft_reset_checked() {
+        let reset = || vec![
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        let mut expectations = reset();
+        expectations.extend(pe_state_transactions(0x18));
+        expectations.extend(pe_state_transactions(0x03));
+        expectations.extend(reset());
+        for _ in 0..2 {
+            expectations.extend(pe_state_transactions(0x18));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.soft_reset_checked(&mut MockNoop::new()).unwrap());
+        stusb.set_poll_config(PollConfig { max_attempts: 2, interval_us: None });
+        assert!(!stusb.soft_reset_checked(&mut MockNoop::new()).unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn contract_changed_across_polls() {
+        let mut expectations = contract_transactions(180, 200, false);
+        expectations.extend(contract_transactions(180, 200, false));
+        expectations.extend(contract_transactions(300, 200, false));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.contract_changed().unwrap().unwrap().voltage_mv, Some(9000));
+        assert!(stusb.contract_changed().unwrap().is_none());
+        assert_eq!(stusb.contract_changed().unwrap().unwrap().voltage_mv, Some(15000));
+        i2c.done();
+    }
+
+    #[test]
+    fn write_nvm_validation() {
+        let mut invalid = nvm::tests::FACTORY_NVM;
+        // No PDOs
+        invalid[0x1A] &= !0x06;
+        let mut sectors = [0u64; NVM_SECTORS];
+        LittleEndian::read_u64_into(&invalid, &mut sectors);
+        let expectations = nvm_write_transactions(&invalid);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.write_nvm_bytes(&invalid, &mut MockNoop::new()), Err(Error::InvalidNvm)));
+        assert!(matches!(stusb.write_nvm(sectors, &mut MockNoop::new()), Err(Error::InvalidNvm)));
+        stusb.write_nvm_unchecked(sectors, &mut MockNoop::new()).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn current_contract_flags() {
+        let rdo = |bits: u32| vec![
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, bits.to_le_bytes().to_vec()),
+        ];
+        let mut expectations = rdo(1 << 28 | 1 << 26 | 50 << 10 | 300);
+        expectations.extend(rdo(1 << 28 | 1 << 26 | 50 << 10 | 300));
+        expectations.extend(rdo(2 << 28 | 1 << 27 | 200 << 10 | 200));
+        expectations.extend(rdo(2 << 28 | 1 << 27 | 200 << 10 | 200));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.current_contract_has_mismatch().unwrap());
+        assert!(!stusb.current_contract_giveback().unwrap());
+        assert!(!stusb.current_contract_has_mismatch().unwrap());
+        assert!(stusb.current_contract_giveback().unwrap());
+        i2c.done();
+    }
+
+    /// Delay adding up the requested time instead of waiting
+    struct TotalDelay(u32);
+
+    impl DelayMs<u16> for TotalDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.0 += ms as u32;
+        }
+    }
+
+    #[test]
+    fn reload_nvm() {
+        let mut expectations = contract_transactions(300, 200, false);
+        expectations.extend([
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x01]),
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x00]),
+        ]);
+        expectations.extend(contract_transactions(300, 200, false));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.contract_changed().unwrap().is_some());
+        let mut delay = TotalDelay(0);
+        stusb.reload_nvm(&mut delay).unwrap();
+        assert_eq!(delay.0, RESET_TIME_MS as u32);
+        // The contract seen before the reset is forgotten
+        assert!(stusb.contract_changed().unwrap().is_some());
+        i2c.done();
+    }
+
+    #[test]
+    fn verify_voltage_tolerance() {
+        let mut expectations = pe_state_transactions(0x18);
+        expectations.extend(contract_transactions(240, 300, false));
+        expectations.extend(pe_state_transactions(0x18));
+        expectations.extend(contract_transactions(180, 300, false));
+        expectations.extend(pe_state_transactions(0x14));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.verify_voltage(12000, 500).unwrap());
+        assert!(!stusb.verify_voltage(12000, 500).unwrap());
+        // No explicit contract
+        assert!(!stusb.verify_voltage(5000, 500).unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn looking_for_connection() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x20]),
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x05]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.is_looking_for_connection().unwrap());
+        assert!(!stusb.is_looking_for_connection().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn set_nvm_pdo_round_trip() {
+        let mut expected = nvm::tests::FACTORY_NVM;
+        // PDO2 at 9V 3A
+        expected[0x21] = 0x2D;
+        expected[0x1C] = 0xFB;
+        let mut expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        // Only the PDO current and voltage sectors are reprogrammed
+        expectations.extend(nvm_write_sectors_transactions(&expected, 0x18));
+        expectations.extend(nvm_read_transactions(&expected));
+        // Programming did not take, the read back still holds the old PDO
+        expectations.extend(nvm_read_transactions(&nvm::tests::FACTORY_NVM));
+        expectations.extend(nvm_write_sectors_transactions(&expected, 0x18));
+        expectations.extend(nvm_read_transactions(&nvm::tests::FACTORY_NVM));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_nvm_pdo(PdoChannel::PDO2, Pdo::new_fixed(180, 300), &mut MockNoop::new()).unwrap();
+        assert!(matches!(stusb.set_nvm_pdo(PdoChannel::PDO2, Pdo::new_fixed(180, 300), &mut MockNoop::new()),
+            Err(Error::NvmVerifyFailed)));
+        let variable = Pdo::from_bits(0x4000_0000 | 180 << 20 | 100 << 10 | 300).unwrap();
+        assert!(matches!(stusb.set_nvm_pdo(PdoChannel::PDO3, variable, &mut MockNoop::new()), Err(Error::NotFixedPdo)));
+        assert!(matches!(stusb.set_nvm_pdo(PdoChannel::PDO1, Pdo::new_fixed(180, 300), &mut MockNoop::new()), Err(Error::InvalidPdo1Voltage)));
+        i2c.done();
+    }
+
+    #[test]
+    fn cc_line_states() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x1C]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_cc_line_states().unwrap(), (CcState::Open, CcState::Power3_0));
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_lock_state() {
+        let state = |password: u8, ctrl: u8| vec![
+            Transaction::write(ADDR, vec![Register::FTPCustPassword as u8]),
+            Transaction::read(ADDR, vec![password]),
+            Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8]),
+            Transaction::read(ADDR, vec![ctrl]),
+        ];
+        let mut expectations = state(0x47, 0xC0);
+        expectations.extend(state(0x00, 0x40));
+        expectations.extend(state(0x47, 0x40));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(!stusb.is_nvm_locked().unwrap());
+        assert!(stusb.is_nvm_locked().unwrap());
+        assert!(stusb.is_nvm_locked().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn request_source_capabilities_response() {
+        let prt = |status: u8| vec![
+            Transaction::write(ADDR, vec![Register::PRTStatus as u8]),
+            Transaction::read(ADDR, vec![status]),
+        ];
+        let header: u16 = 2 << 12 | 0x1;
+        let source_caps = || {
+            let mut caps = Vec::new();
+            caps.extend_from_slice(&(100u32 << 10 | 300).to_le_bytes());
+            caps.extend_from_slice(&(300u32 << 10 | 300).to_le_bytes());
+            vec![
+                Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+                Transaction::read(ADDR, header.to_le_bytes().to_vec()),
+                Transaction::write(ADDR, vec![Register::RXDataObj as u8]),
+                Transaction::read(ADDR, caps),
+            ]
+        };
+        let mut expectations = prt(0x00);
+        expectations.extend(vec![
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x07]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ]);
+        expectations.extend(prt(0x00));
+        expectations.extend(prt(0x00));
+        expectations.extend(prt(0x04));
+        expectations.push(Transaction::write(ADDR, vec![Register::RXHeaderL as u8]));
+        expectations.push(Transaction::read(ADDR, header.to_le_bytes().to_vec()));
+        expectations.extend(source_caps());
+        expectations.extend(prt(0x00));
+        expectations.push(Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x07]));
+        expectations.push(Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]));
+        expectations.extend(prt(0x00));
+        expectations.extend(prt(0x00));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let caps = stusb.request_source_capabilities(&mut MockNoop::new(), 10).unwrap();
+        assert_eq!(caps.as_slice(), &[Pdo::new_fixed(100, 300), Pdo::new_fixed(300, 300)]);
+        assert!(matches!(stusb.request_source_capabilities(&mut MockNoop::new(), 1), Err(Error::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn request_source_capabilities_clears_stale_message() {
+        let prt = |status: u8| vec![
+            Transaction::write(ADDR, vec![Register::PRTStatus as u8]),
+            Transaction::read(ADDR, vec![status]),
+        ];
+        // MsgReceived is still latched from earlier traffic
+        let mut expectations = prt(0x04);
+        expectations.extend(vec![
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x07]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ]);
+        expectations.extend(prt(0x00));
+        expectations.extend(prt(0x00));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.request_source_capabilities(&mut MockNoop::new(), 1), Err(Error::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn pd_message_pending_and_clear() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x02]),
+            Transaction::write(ADDR, vec![Register::PRTStatus as u8]),
+            Transaction::read(ADDR, vec![0x04]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.pd_message_pending().unwrap());
+        assert!(stusb.clear_pd_message().unwrap());
+        assert!(!stusb.pd_message_pending().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn vbus_band() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus0 as u8]),
+            Transaction::read(ADDR, vec![0x20, 0x0A]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_vbus_band().unwrap(), VbusBand::AboveHigh);
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_session_locks_on_drop() {
+        let sector = &nvm::tests::FACTORY_NVM[24..32];
+        let mut expectations = nvm_enter_transactions();
+        expectations.extend(nvm_read_sector_transactions(3, sector));
+        expectations.extend(nvm_exit_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        {
+            let mut session = stusb.nvm_session().unwrap();
+            assert_eq!(session.read_sector(3, &mut MockNoop::new()).unwrap(), sector);
+            assert!(matches!(session.read_sector(5, &mut MockNoop::new()), Err(Error::OutaRangeValue)));
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn address_strap_table() {
+        let table = Address::strap_table();
+        assert_eq!(table[0], (false, false, 0x28));
+        assert_eq!(table[1], (false, true, 0x29));
+        assert_eq!(table[2], (true, false, 0x2A));
+        assert_eq!(table[3], (true, true, 0x2B));
+    }
+
+    #[test]
+    fn get_nvm_config_checked() {
+        let mut corrupted = nvm::tests::FACTORY_NVM;
+        corrupted[0x24] |= 0x08;
+        let mut expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        expectations.extend(nvm_read_transactions(&corrupted));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_nvm_config_checked(&mut MockNoop::new()).unwrap().to_bytes(), nvm::tests::FACTORY_NVM);
+        assert!(matches!(stusb.get_nvm_config_checked(&mut MockNoop::new()), Err(Error::ImplausibleNvm)));
+        i2c.done();
+    }
+
+    #[test]
+    fn negotiated_pd_revision() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x41, 0x20]),
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, vec![0x81, 0x20]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.negotiated_pd_revision().unwrap(), Some(2));
+        assert_eq!(stusb.negotiated_pd_revision().unwrap(), Some(3));
+        i2c.done();
+    }
+
+    #[test]
+    fn limit_max_voltage() {
+        let pdos = [Pdo::new_fixed(100, 300), Pdo::new_fixed(180, 300), Pdo::new_fixed(300, 300)];
+        let live = |reads: usize| live_pdo_transactions(3, &pdos[..reads]);
+        let renegotiate = |num: u8| vec![
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, num]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        let mut expectations = live(3);
+        expectations.extend(renegotiate(2));
+        expectations.extend(live(2));
+        expectations.extend(renegotiate(1));
+        expectations.extend(live(1));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.limit_max_voltage(9000).unwrap();
+        stusb.limit_max_voltage(5000).unwrap();
+        assert!(matches!(stusb.limit_max_voltage(3300), Err(Error::OutaRangeValue)));
+        i2c.done();
+    }
+
+    #[cfg(feature = "log")]
+    mod trace {
+        use std::string::{String, ToString};
+        use std::sync::Mutex;
+        use std::vec::Vec;
+        use embedded_hal_mock::i2c::{Mock, Transaction};
+        use crate::*;
+
+        static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        struct CapturingLogger;
+
+        impl log::Log for CapturingLogger {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                EVENTS.lock().unwrap().push(record.args().to_string());
+            }
+            fn flush(&self) {}
+        }
+
+        #[test]
+        fn events_emitted() {
+            let _ = log::set_logger(&CapturingLogger);
+            log::set_max_level(log::LevelFilter::Trace);
+            let expectations = [
+                Transaction::write(super::ADDR, std::vec![Register::TXHeaderL as u8, 0x0D]),
+                Transaction::write(super::ADDR, std::vec![Register::PDCommandCtrl as u8, 0x26]),
+            ];
+            let mut i2c = Mock::new(&expectations);
+            let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+            stusb.soft_reset().unwrap();
+            i2c.done();
+            assert!(EVENTS.lock().unwrap().iter().any(|e| e == "soft_reset"));
+        }
+    }
+
+    #[test]
+    fn source_pd_capable() {
+        let header = |value: u16| vec![
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, value.to_le_bytes().to_vec()),
+        ];
+        let mut expectations = pe_state_transactions(0x18);
+        // Waiting for capabilities with source caps in the RX buffer
+        expectations.extend(pe_state_transactions(0x14));
+        expectations.extend(header(2 << 12 | 0x1));
+        // Type-C only charger
+        expectations.extend(pe_state_transactions(0x14));
+        expectations.extend(header(0x0000));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.source_is_pd_capable().unwrap());
+        assert!(stusb.source_is_pd_capable().unwrap());
+        assert!(!stusb.source_is_pd_capable().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn nvm_sector_round_trip() {
+        let data = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF];
+        let mut expectations = nvm_enter_transactions();
+        let mut buffer = vec![Register::RWBuffer as u8];
+        buffer.extend_from_slice(&data);
+        expectations.push(Transaction::write(ADDR, buffer));
+        expectations.push(Transaction::write(ADDR, vec![Register::FTPCtrl0 as u8, 0xC0]));
+        expectations.extend(nvm_request_transactions(0x01, 0));
+        expectations.extend(nvm_request_transactions(0x06, 2));
+        expectations.extend(nvm_read_sector_transactions(2, &data));
+        expectations.extend(nvm_exit_transactions());
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        {
+            let mut session = stusb.nvm_session().unwrap();
+            session.write_sector(2, &data, &mut MockNoop::new()).unwrap();
+            assert_eq!(session.read_sector(2, &mut MockNoop::new()).unwrap(), data);
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn operating_mode() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PortStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x41]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.operating_mode().unwrap(), OperatingMode::Sink);
+        i2c.done();
+    }
+
+    #[test]
+    fn powered_cable() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PortStatus1 as u8]),
+            Transaction::read(ADDR, vec![0xA1]),
+            Transaction::write(ADDR, vec![Register::PortStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x41]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.is_powered_cable().unwrap());
+        assert!(!stusb.is_powered_cable().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn ensure_nvm_config_writes_only_changes() {
+        let mut desired = NvmConfig::from_bytes(nvm::tests::FACTORY_NVM);
+        let mut expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        expectations.extend(nvm_read_transactions(&nvm::tests::FACTORY_NVM));
+        desired.set_voltage_upper_limit(PdoChannel::PDO1, 10);
+        expectations.extend(nvm_write_transactions(&desired.to_bytes()));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(!stusb.ensure_nvm_config(&NvmConfig::from_bytes(nvm::tests::FACTORY_NVM), &mut MockNoop::new()).unwrap());
+        assert!(stusb.ensure_nvm_config(&desired, &mut MockNoop::new()).unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn rx_object_count() {
+        let header: u16 = 3 << 12 | 0x41;
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::RXHeaderL as u8]),
+            Transaction::read(ADDR, header.to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_rx_object_count().unwrap(), 3);
+        i2c.done();
+    }
+
+    #[test]
+    fn apply_profile_sequence_and_validation() {
+        let pdos = [Pdo::new_fixed(100, 300), Pdo::new_fixed(180, 200), Pdo::new_fixed(240, 150)];
+        let mut expected = NvmConfig::from_bytes(nvm::tests::FACTORY_NVM);
+        expected.set_flex_current(150);
+        // Only the flex current sector is programmed, before the live PDOs
+        // are touched
+        let mut expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        expectations.extend(nvm_write_sectors_transactions(&expected.to_bytes(), 0x10));
+        for (reg, pdo) in [Register::DPMSNKPDO1, Register::DPMSNKPDO2, Register::DPMSNKPDO3].iter().zip(pdos.iter()) {
+            let mut data = vec![*reg as u8];
+            data.extend_from_slice(&pdo.bits().to_le_bytes());
+            expectations.push(Transaction::write(ADDR, data));
+        }
+        expectations.push(Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 3]));
+        expectations.push(Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]));
+        expectations.push(Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.apply_profile(&pdos, Some(1500), &mut MockNoop::new()).unwrap();
+        // Rejected without any write
+        let variable = Pdo::from_bits(0x4000_0000 | 180 << 20 | 100 << 10 | 300).unwrap();
+        assert!(matches!(stusb.apply_profile(&[pdos[0], variable], None, &mut MockNoop::new()), Err(Error::NotFixedPdo)));
+        assert!(matches!(stusb.apply_profile(&pdos[1..], None, &mut MockNoop::new()), Err(Error::InvalidPdo1Voltage)));
+        assert!(matches!(stusb.apply_profile(&pdos, Some(5010), &mut MockNoop::new()), Err(Error::OutaRangeValue)));
+        assert!(matches!(stusb.apply_profile(&pdos, Some(1505), &mut MockNoop::new()), Err(Error::OutaRangeValue)));
+        i2c.done();
+
+        // Invalid NVM contents are caught before the live PDOs are written
+        let expectations = nvm_read_transactions(&[0x00; NVM_SIZE]);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.apply_profile(&pdos, Some(1500), &mut MockNoop::new()), Err(Error::InvalidNvm)));
+        i2c.done();
+    }
+
+    #[test]
+    fn write_nvm_bytes_logged() {
+        let expectations = nvm_write_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let written = stusb.write_nvm_bytes_logged(&nvm::tests::FACTORY_NVM, &mut MockNoop::new()).unwrap();
+        assert_eq!(written, nvm::tests::FACTORY_NVM);
+        i2c.done();
+    }
+
+    #[test]
+    fn requested_vbus() {
+        let expectations = contract_transactions(300, 200, true);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_requested_vbus_mv().unwrap(), Some(15000));
+        i2c.done();
+    }
+
+    #[test]
+    fn available_current_pd_contract() {
+        let rdo: u32 = 2 << 28 | 300 << 10 | 150;
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x18]),
+            Transaction::write(ADDR, vec![Register::RDORegStatus as u8]),
+            Transaction::read(ADDR, rdo.to_le_bytes().to_vec()),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.available_current_ma().unwrap(), 1500);
+        i2c.done();
+    }
+
+    #[test]
+    fn available_current_typec_only() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x14]),
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x02]),
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::CCStatus as u8]),
+            Transaction::read(ADDR, vec![0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.available_current_ma().unwrap(), 1500);
+        assert_eq!(stusb.available_current_ma().unwrap(), 500);
+        i2c.done();
+    }
+
+    #[cfg(feature = "std")]
+    fn detached_snapshot_transactions(monitoring: u8) -> Vec<Transaction> {
+        vec![
+            Transaction::write(ADDR, vec![Register::PortStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCStatus as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::TypeCMonitoringStatus1 as u8]),
+            Transaction::read(ADDR, vec![monitoring]),
+            Transaction::write(ADDR, vec![Register::PEFSM as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ]
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn monitor_reports_only_changes() {
+        let mut expectations = detached_snapshot_transactions(0x00);
+        expectations.extend(detached_snapshot_transactions(0x00));
+        expectations.extend(detached_snapshot_transactions(MonitoringStatus::VbusValidSnk.bits()));
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let mut seen = Vec::new();
+        stusb.monitor_changes(std::time::Duration::from_millis(0), |s| {
+            seen.push(s.monitoring);
+            seen.len() < 2
+        }).unwrap();
+        assert_eq!(seen, [MonitoringStatus::empty(), MonitoringStatus::VbusValidSnk]);
+        i2c.done();
+    }
+
+    #[test]
+    fn write_nvm_diff_skips_unchanged() {
+        let expectations = nvm_read_transactions(&nvm::tests::FACTORY_NVM);
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        let factory = NvmConfig::from_bytes(nvm::tests::FACTORY_NVM);
+        assert_eq!(stusb.write_nvm_diff(&factory, &mut MockNoop::new()).unwrap(), 0);
+        i2c.done();
+    }
+
+    #[test]
+    fn any_alert_pending() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x40]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8]),
+            Transaction::read(ADDR, vec![0x10]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x40]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8]),
+            Transaction::read(ADDR, vec![0x40]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1 as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::AlertStatus1Mask as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(stusb.any_alert_pending().unwrap());
+        assert!(!stusb.any_alert_pending().unwrap());
+        assert!(!stusb.any_alert_pending().unwrap());
+        i2c.done();
+    }
+
+    #[test]
+    fn profile_modes() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 1]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8, 3]),
+            Transaction::write(ADDR, vec![Register::TXHeaderL as u8, 0x0D]),
+            Transaction::write(ADDR, vec![Register::PDCommandCtrl as u8, 0x26]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_profile_mode(ProfileMode::Minimal).unwrap();
+        stusb.set_profile_mode(ProfileMode::Full).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn full_reset_waits_for_device_id() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x01]),
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x00]),
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x25]),
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x01]),
+            Transaction::write(ADDR, vec![Register::ResetCtrl as u8, 0x00]),
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+            Transaction::write(ADDR, vec![Register::DeviceId as u8]),
+            Transaction::read(ADDR, vec![0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        stusb.set_poll_config(PollConfig { max_attempts: 2, interval_us: None });
+        stusb.full_reset(&mut MockNoop::new()).unwrap();
+        assert!(matches!(stusb.full_reset(&mut MockNoop::new()), Err(Error::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn dpm_pdo_numb_raw() {
+        let expectations = [
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![0x0B]),
+            Transaction::write(ADDR, vec![Register::DPMPDONumb as u8]),
+            Transaction::read(ADDR, vec![0x0B]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert_eq!(stusb.get_dpm_pdo_numb_raw().unwrap(), 0x0B);
+        assert_eq!(stusb.get_live_num_pdo().unwrap(), 3);
+        i2c.done();
+    }
+
+    #[test]
+    fn write_regs_rejects_long_input() {
+        let mut i2c = Mock::new(&[]);
+        let mut stusb = STUSB4500::new(i2c.clone(), Address::Default);
+        assert!(matches!(stusb.write_regs(Register::RWBuffer, &[0x00; 9]), Err(Error::OutaRangeValue)));
+        i2c.done();
     }
 }
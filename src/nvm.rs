@@ -0,0 +1,493 @@
+#[cfg(feature = "std")]
+use std::string::String;
+
+use hal::blocking::delay::DelayUs;
+use hal::blocking::i2c;
+
+use crate::registers::Register;
+use crate::{Error, PdoChannel, Stusb4500Result, STUSB4500};
+
+/// Password unlocking customer access to the NVM
+pub(crate) const FTP_CUST_PASSWORD: u8 = 0x47;
+
+// FTP_CTRL_0 bits
+pub(crate) const FTP_CUST_PWR: u8 = 0x80;
+pub(crate) const FTP_CUST_RST_N: u8 = 0x40;
+pub(crate) const FTP_CUST_REQ: u8 = 0x10;
+pub(crate) const FTP_CUST_SECT: u8 = 0x07;
+
+// FTP_CTRL_1 fields and opcodes
+pub(crate) const FTP_CUST_SER: u8 = 0xF8;
+pub(crate) const FTP_CUST_OPCODE: u8 = 0x07;
+pub(crate) const NVM_OP_READ: u8 = 0x00;
+pub(crate) const NVM_OP_WRITE_PL: u8 = 0x01;
+pub(crate) const NVM_OP_WRITE_SER: u8 = 0x02;
+pub(crate) const NVM_OP_ERASE_SECTOR: u8 = 0x05;
+pub(crate) const NVM_OP_PROG_SECTOR: u8 = 0x06;
+pub(crate) const NVM_OP_SOFT_PROG_SECTOR: u8 = 0x07;
+
+/// Sector erase mask selecting all sectors
+pub(crate) const NVM_ALL_SECTORS: u8 = 0x1F;
+
+/// Number of NVM sectors
+pub const NVM_SECTORS: usize = 5;
+/// Size of a NVM sector in bytes
+pub const NVM_SECTOR_SIZE: usize = 8;
+/// Size of the whole NVM in bytes
+pub const NVM_SIZE: usize = NVM_SECTORS * NVM_SECTOR_SIZE;
+
+// Sectors are transferred through `RW_BUFFER` (0x53-0x5A), which must not
+// overlap the TX header below it or the DPM registers above it
+const _: () = assert!((Register::TXHeaderH as usize) < Register::RWBuffer as usize);
+const _: () = assert!(Register::RWBuffer as usize + NVM_SECTOR_SIZE <= Register::DPMPDONumb as usize);
+
+/// NVM sectors, each packed as a little-endian u64 as returned by `get_nvm`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NvmSectors {
+    /// Factory trimming, do not modify
+    pub sector0: u64,
+    /// GPIO and VBUS discharge configuration
+    pub sector1: u64,
+    /// Factory calibration, do not modify
+    pub sector2: u64,
+    /// Number of PDOs, PDO currents and VBUS monitoring thresholds
+    pub sector3: u64,
+    /// PDO2 and PDO3 voltages, flexible current and POWER_OK configuration
+    pub sector4: u64,
+}
+
+impl NvmSectors {
+    pub fn from_raw(raw: [u64; NVM_SECTORS]) -> Self {
+        NvmSectors {
+            sector0: raw[0],
+            sector1: raw[1],
+            sector2: raw[2],
+            sector3: raw[3],
+            sector4: raw[4],
+        }
+    }
+
+    pub fn to_raw(&self) -> [u64; NVM_SECTORS] {
+        [self.sector0, self.sector1, self.sector2, self.sector3, self.sector4]
+    }
+}
+
+/// Decoded view of the NVM contents.
+/// Wraps the raw bytes so unknown/reserved bits round-trip untouched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NvmConfig {
+    bytes: [u8; NVM_SIZE],
+}
+
+impl NvmConfig {
+    pub fn from_bytes(bytes: [u8; NVM_SIZE]) -> Self {
+        NvmConfig { bytes }
+    }
+
+    pub fn to_bytes(&self) -> [u8; NVM_SIZE] {
+        self.bytes
+    }
+
+    /// Bit mask of the sectors whose bytes differ from `other`, bit n for
+    /// sector n. PDO changes only touch sectors 3 and 4.
+    pub fn changed_sectors(&self, other: &NvmConfig) -> u8 {
+        let mut mask = 0;
+        for (sector, (a, b)) in self.bytes.chunks(NVM_SECTOR_SIZE)
+            .zip(other.bytes.chunks(NVM_SECTOR_SIZE)).enumerate() {
+            if a != b {
+                mask |= 1 << sector;
+            }
+        }
+        mask
+    }
+
+    /// Export in the line-per-byte hex text format used by ST's STSW-STUSB002 GUI:
+    /// one `0xXX` byte per line, in wire order starting with sector 0.
+    #[cfg(feature = "std")]
+    pub fn to_st_gui_string(&self) -> String {
+        use core::fmt::Write;
+        let mut s = String::new();
+        for b in self.bytes.iter() {
+            // Writing to a String cannot fail
+            let _ = writeln!(s, "0x{:02X}", b);
+        }
+        s
+    }
+
+    /// Import the format written by `to_st_gui_string`. The `0x` prefix is
+    /// optional and blank lines are skipped. Returns `None` unless exactly
+    /// 40 valid bytes are found.
+    #[cfg(feature = "std")]
+    pub fn from_st_gui_string(s: &str) -> Option<Self> {
+        let mut bytes = [0x00; NVM_SIZE];
+        let mut count = 0;
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let hex = line.trim_start_matches("0x").trim_start_matches("0X");
+            *bytes.get_mut(count)? = u8::from_str_radix(hex, 16).ok()?;
+            count += 1;
+        }
+        if count == NVM_SIZE {
+            Some(NvmConfig::from_bytes(bytes))
+        } else {
+            None
+        }
+    }
+
+    /// Number of sink PDOs loaded into `DPM_PDO_NUMB` on reset.
+    /// Sector 3, byte 2, bits 2:1.
+    pub fn num_pdo(&self) -> u8 {
+        (self.bytes[0x1A] & 0x06) >> 1
+    }
+
+    /// Voltage of a sink PDO in 50mV steps. PDO1 is always 5V and not stored.
+    pub fn pdo_voltage(&self, ch: PdoChannel) -> u32 {
+        match ch {
+            PdoChannel::PDO1 => 100,
+            PdoChannel::PDO2 => (self.bytes[0x21] as u32) << 2 | (self.bytes[0x20] >> 6) as u32,
+            PdoChannel::PDO3 => ((self.bytes[0x23] & 0x03) as u32) << 8 | self.bytes[0x22] as u32,
+        }
+    }
+
+    /// Current of a sink PDO in 10mA steps.
+    /// Stored as a 4 bit code in sector 3, code 0 selects the shared flexible current.
+    pub fn pdo_current(&self, ch: PdoChannel) -> u32 {
+        let code = match ch {
+            PdoChannel::PDO1 => self.bytes[0x1A] >> 4,
+            PdoChannel::PDO2 => self.bytes[0x1C] & 0x0F,
+            PdoChannel::PDO3 => self.bytes[0x1D] >> 4,
+        } as u32;
+        match code {
+            0 => self.flex_current(),
+            1..=10 => 25 * code + 25,
+            _ => 50 * code - 250,
+        }
+    }
+
+    /// Set the voltage of PDO2 or PDO3 in 50mV steps, does nothing for PDO1
+    pub fn set_pdo_voltage(&mut self, ch: PdoChannel, voltage: u32) {
+        match ch {
+            PdoChannel::PDO1 => {},
+            PdoChannel::PDO2 => {
+                self.bytes[0x20] = (self.bytes[0x20] & 0x3F) | ((voltage & 0x03) as u8) << 6;
+                self.bytes[0x21] = (voltage >> 2) as u8;
+            },
+            PdoChannel::PDO3 => {
+                self.bytes[0x22] = voltage as u8;
+                self.bytes[0x23] = (self.bytes[0x23] & 0xFC) | ((voltage >> 8) & 0x03) as u8;
+            },
+        }
+    }
+
+    /// Set the current of a PDO in 10mA steps. Rounded down to the nearest
+    /// current code, at least 0.5A and at most 5A.
+    pub fn set_pdo_current(&mut self, ch: PdoChannel, current: u32) {
+        let code = match current {
+            0..=299 => (current.max(50) - 25) / 25,
+            _ => (current.min(500) + 250) / 50,
+        } as u8;
+        match ch {
+            PdoChannel::PDO1 => self.bytes[0x1A] = (self.bytes[0x1A] & 0x0F) | code << 4,
+            PdoChannel::PDO2 => self.bytes[0x1C] = (self.bytes[0x1C] & 0xF0) | code,
+            PdoChannel::PDO3 => self.bytes[0x1D] = (self.bytes[0x1D] & 0x0F) | code << 4,
+        }
+    }
+
+    /// Flexible current shared by PDOs with current code 0, in 10mA steps
+    pub fn flex_current(&self) -> u32 {
+        ((self.bytes[0x24] & 0x0F) as u32) << 6 | ((self.bytes[0x23] & 0xFC) >> 2) as u32
+    }
+
+    /// Set the flexible current in 10mA steps, a 10 bit field in sector 4
+    pub fn set_flex_current(&mut self, current: u32) {
+        self.bytes[0x23] = (self.bytes[0x23] & 0x03) | ((current & 0x3F) as u8) << 2;
+        self.bytes[0x24] = (self.bytes[0x24] & 0xF0) | ((current >> 6) & 0x0F) as u8;
+    }
+
+    /// Check the configuration is safe to program and detect corrupted reads:
+    /// the number of PDOs (1-3), the voltages (5-20V) and currents (at most
+    /// 5A) of the used PDOs and the flexible current (at most 5A) must be in
+    /// range. Unused PDO slots are not checked.
+    pub fn is_valid(&self) -> bool {
+        (1..=3).contains(&self.num_pdo())
+            && PdoChannel::ALL.iter().take(self.num_pdo() as usize).all(|ch| {
+                (100..=400).contains(&self.pdo_voltage(*ch)) && self.pdo_current(*ch) <= 500
+            })
+            && self.flex_current() <= 500
+    }
+
+    /// Upper VBUS monitoring threshold of a PDO in percent (5-20%) above its voltage.
+    /// Stored as `percent - 5` in a 4 bit field of sector 3.
+    pub fn voltage_upper_limit(&self, ch: PdoChannel) -> u8 {
+        let nibble = match ch {
+            PdoChannel::PDO1 => self.bytes[0x1B] >> 4,
+            PdoChannel::PDO2 => self.bytes[0x1D] & 0x0F,
+            PdoChannel::PDO3 => self.bytes[0x1E] >> 4,
+        };
+        nibble + 5
+    }
+
+    pub fn set_voltage_upper_limit(&mut self, ch: PdoChannel, percent: u8) {
+        let nibble = percent.saturating_sub(5) & 0x0F;
+        match ch {
+            PdoChannel::PDO1 => self.bytes[0x1B] = (self.bytes[0x1B] & 0x0F) | nibble << 4,
+            PdoChannel::PDO2 => self.bytes[0x1D] = (self.bytes[0x1D] & 0xF0) | nibble,
+            PdoChannel::PDO3 => self.bytes[0x1E] = (self.bytes[0x1E] & 0x0F) | nibble << 4,
+        }
+    }
+
+    /// Lower VBUS monitoring threshold of a PDO in percent (5-20%) below its voltage.
+    /// `None` for PDO1, whose lower threshold is fixed by the chip.
+    pub fn voltage_lower_limit(&self, ch: PdoChannel) -> Option<u8> {
+        match ch {
+            PdoChannel::PDO1 => None,
+            PdoChannel::PDO2 => Some((self.bytes[0x1C] >> 4) + 5),
+            PdoChannel::PDO3 => Some((self.bytes[0x1E] & 0x0F) + 5),
+        }
+    }
+
+    /// Set the lower VBUS monitoring threshold, does nothing for PDO1
+    pub fn set_voltage_lower_limit(&mut self, ch: PdoChannel, percent: u8) {
+        let nibble = percent.saturating_sub(5) & 0x0F;
+        match ch {
+            PdoChannel::PDO1 => {},
+            PdoChannel::PDO2 => self.bytes[0x1C] = (self.bytes[0x1C] & 0x0F) | nibble << 4,
+            PdoChannel::PDO3 => self.bytes[0x1E] = (self.bytes[0x1E] & 0xF0) | nibble,
+        }
+    }
+}
+
+/// Field by field comparison of two NVM configurations, true where they match.
+/// Per channel arrays are indexed PDO1 to PDO3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfigComparison {
+    /// All 40 bytes match, including reserved and undecoded bits
+    pub identical: bool,
+    pub num_pdo: bool,
+    pub pdo_voltage: [bool; 3],
+    pub pdo_current: [bool; 3],
+    pub voltage_upper_limit: [bool; 3],
+    pub voltage_lower_limit: [bool; 3],
+}
+
+/// Compare the decoded fields of two NVM configurations
+pub fn compare_configs(a: &NvmConfig, b: &NvmConfig) -> ConfigComparison {
+    let per_channel = |f: &dyn Fn(&NvmConfig, PdoChannel) -> u32| {
+        let mut matches = [false; 3];
+        for (m, ch) in matches.iter_mut().zip(PdoChannel::ALL.iter()) {
+            *m = f(a, *ch) == f(b, *ch);
+        }
+        matches
+    };
+    ConfigComparison {
+        identical: a.bytes == b.bytes,
+        num_pdo: a.num_pdo() == b.num_pdo(),
+        pdo_voltage: per_channel(&|c, ch| c.pdo_voltage(ch)),
+        pdo_current: per_channel(&|c, ch| c.pdo_current(ch)),
+        voltage_upper_limit: per_channel(&|c, ch| c.voltage_upper_limit(ch) as u32),
+        voltage_lower_limit: per_channel(&|c, ch| c.voltage_lower_limit(ch).unwrap_or(0) as u32),
+    }
+}
+
+/// Unlocked NVM, returned by `STUSB4500::nvm_session`.
+/// The NVM is locked again when the session is dropped, also on early return
+/// or panic.
+pub struct NvmSession<'a, I2C>
+    where I2C: i2c::Write + i2c::Read<Error = <I2C as i2c::Write>::Error> {
+    pub(crate) dev: &'a mut STUSB4500<I2C>,
+}
+
+impl<'a, I2C, E> NvmSession<'a, I2C>
+    where I2C: i2c::Write<Error = E> + i2c::Read<Error = E> {
+
+    pub fn read_sector<D: DelayUs<u32>>(&mut self, sector: u8, delay: &mut D) -> Stusb4500Result<[u8; NVM_SECTOR_SIZE], E> {
+        if sector as usize >= NVM_SECTORS {
+            return Err(Error::OutaRangeValue);
+        }
+        let mut buf = [0x00; NVM_SECTOR_SIZE];
+        self.dev.nvm_read_sector(sector, &mut buf, delay)?;
+        Ok(buf)
+    }
+
+    pub fn read_all<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<[u8; NVM_SIZE], E> {
+        self.dev.nvm_read_all(delay)
+    }
+
+    /// Erase all sectors
+    pub fn erase<D: DelayUs<u32>>(&mut self, delay: &mut D) -> Stusb4500Result<(), E> {
+        self.dev.nvm_erase(NVM_ALL_SECTORS, delay)
+    }
+
+    /// Program one sector, which must have been erased
+    pub fn write_sector<D: DelayUs<u32>>(&mut self, sector: u8, data: &[u8; NVM_SECTOR_SIZE], delay: &mut D) -> Stusb4500Result<(), E> {
+        if sector as usize >= NVM_SECTORS {
+            return Err(Error::OutaRangeValue);
+        }
+        self.dev.nvm_write_sector(sector, data, delay)
+    }
+}
+
+impl<'a, I2C> Drop for NvmSession<'a, I2C>
+    where I2C: i2c::Write + i2c::Read<Error = <I2C as i2c::Write>::Error> {
+    fn drop(&mut self) {
+        // Errors cannot be reported from drop
+        let _ = self.dev.lock_nvm();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::nvm::*;
+
+    /// Factory default NVM of the STUSB4500
+    pub(crate) const FACTORY_NVM: [u8; NVM_SIZE] = [
+        0x00, 0x00, 0xB0, 0xAA, 0x00, 0x45, 0x00, 0x00,
+        0x10, 0x40, 0x9C, 0x1C, 0xFF, 0x01, 0x3C, 0xDF,
+        0x02, 0x40, 0x0F, 0x00, 0x32, 0x00, 0xFC, 0xF1,
+        0x00, 0x19, 0x56, 0xAF, 0xF5, 0x35, 0x5F, 0x00,
+        0x00, 0x4B, 0x90, 0x21, 0x43, 0x00, 0x40, 0xFB,
+    ];
+
+    #[test]
+    fn sectors_from_raw() {
+        let raw = [0, 1, 2, 0x35F5_AF56_1900_0000, 4];
+        let sectors = NvmSectors::from_raw(raw);
+        assert_eq!(sectors.sector1, 1);
+        assert_eq!(sectors.sector3, 0x35F5_AF56_1900_0000);
+        assert_eq!(sectors.sector4, 4);
+        assert_eq!(sectors.to_raw(), raw);
+    }
+
+    #[test]
+    fn factory_num_pdo() {
+        assert_eq!(NvmConfig::from_bytes(FACTORY_NVM).num_pdo(), 3);
+    }
+
+    #[test]
+    fn factory_pdos() {
+        let nvm = NvmConfig::from_bytes(FACTORY_NVM);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO1), 100);
+        assert_eq!(nvm.pdo_current(PdoChannel::PDO1), 150);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO2), 300);
+        assert_eq!(nvm.pdo_current(PdoChannel::PDO2), 150);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO3), 400);
+        assert_eq!(nvm.pdo_current(PdoChannel::PDO3), 100);
+        let mut bytes = FACTORY_NVM;
+        // Current code 0 selects the 2A flexible current
+        bytes[0x1D] &= 0x0F;
+        assert_eq!(NvmConfig::from_bytes(bytes).pdo_current(PdoChannel::PDO3), 200);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(NvmConfig::from_bytes(FACTORY_NVM).is_valid());
+        let mut bytes = FACTORY_NVM;
+        // No PDOs
+        bytes[0x1A] &= !0x06;
+        assert!(!NvmConfig::from_bytes(bytes).is_valid());
+        let mut bytes = FACTORY_NVM;
+        // PDO3 at 25V
+        bytes[0x22] = 0xF4;
+        assert!(!NvmConfig::from_bytes(bytes).is_valid());
+        let mut bytes = FACTORY_NVM;
+        // Flipped bit in the flexible current
+        bytes[0x24] |= 0x08;
+        assert!(!NvmConfig::from_bytes(bytes).is_valid());
+        let mut bytes = FACTORY_NVM;
+        // PDO2 read as 0V
+        bytes[0x21] = 0x00;
+        assert!(!NvmConfig::from_bytes(bytes).is_valid());
+        // Single PDO with the unused slots zeroed
+        let mut config = NvmConfig::from_bytes(bytes);
+        config.bytes[0x1A] = (config.bytes[0x1A] & !0x06) | 1 << 1;
+        config.set_pdo_voltage(PdoChannel::PDO3, 0);
+        assert!(config.is_valid());
+    }
+
+    #[test]
+    fn pdo_round_trip() {
+        let mut nvm = NvmConfig::from_bytes(FACTORY_NVM);
+        nvm.set_pdo_voltage(PdoChannel::PDO3, 241);
+        nvm.set_pdo_current(PdoChannel::PDO3, 240);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO3), 241);
+        assert_eq!(nvm.pdo_current(PdoChannel::PDO3), 225);
+        for (current, expected) in [(10, 50), (275, 275), (300, 300), (420, 400), (900, 500)].iter() {
+            nvm.set_pdo_current(PdoChannel::PDO1, *current);
+            assert_eq!(nvm.pdo_current(PdoChannel::PDO1), *expected);
+        }
+        nvm.set_flex_current(321);
+        assert_eq!(nvm.flex_current(), 321);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO3), 241);
+        nvm.set_pdo_voltage(PdoChannel::PDO2, 181);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO2), 181);
+        assert_eq!(nvm.pdo_voltage(PdoChannel::PDO3), 241);
+    }
+
+    #[test]
+    fn factory_voltage_limits() {
+        let nvm = NvmConfig::from_bytes(FACTORY_NVM);
+        assert_eq!(nvm.voltage_upper_limit(PdoChannel::PDO1), 15);
+        assert_eq!(nvm.voltage_lower_limit(PdoChannel::PDO1), None);
+        assert_eq!(nvm.voltage_lower_limit(PdoChannel::PDO2), Some(20));
+        assert_eq!(nvm.voltage_upper_limit(PdoChannel::PDO2), 10);
+        assert_eq!(nvm.voltage_lower_limit(PdoChannel::PDO3), Some(20));
+        assert_eq!(nvm.voltage_upper_limit(PdoChannel::PDO3), 10);
+    }
+
+    #[test]
+    fn voltage_limits_round_trip() {
+        let mut nvm = NvmConfig::from_bytes(FACTORY_NVM);
+        nvm.set_voltage_lower_limit(PdoChannel::PDO3, 7);
+        nvm.set_voltage_upper_limit(PdoChannel::PDO3, 12);
+        assert_eq!(nvm.voltage_lower_limit(PdoChannel::PDO3), Some(7));
+        assert_eq!(nvm.voltage_upper_limit(PdoChannel::PDO3), 12);
+        // Nothing else changed
+        let mut expected = FACTORY_NVM;
+        expected[0x1E] = 0x72;
+        assert_eq!(nvm.to_bytes(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn st_gui_string_round_trip() {
+        let nvm = NvmConfig::from_bytes(FACTORY_NVM);
+        let s = nvm.to_st_gui_string();
+        assert!(s.starts_with("0x00\n0x00\n0xB0\n0xAA\n"));
+        assert_eq!(s.lines().count(), NVM_SIZE);
+        assert_eq!(NvmConfig::from_st_gui_string(&s), Some(nvm));
+        assert_eq!(NvmConfig::from_st_gui_string("0x00\n0x01\n"), None);
+    }
+
+    #[test]
+    fn compare_identical_and_differing() {
+        let a = NvmConfig::from_bytes(FACTORY_NVM);
+        let same = compare_configs(&a, &a);
+        assert!(same.identical && same.num_pdo);
+        assert_eq!(same.pdo_voltage, [true; 3]);
+        assert_eq!(same.voltage_lower_limit, [true; 3]);
+
+        let mut b = a;
+        b.set_voltage_upper_limit(PdoChannel::PDO2, 12);
+        let mut bytes = b.to_bytes();
+        // PDO3 at 15V
+        bytes[0x22] = 0x2C;
+        bytes[0x23] &= !0x03;
+        let diff = compare_configs(&a, &NvmConfig::from_bytes(bytes));
+        assert!(!diff.identical);
+        assert!(diff.num_pdo);
+        assert_eq!(diff.pdo_voltage, [true, true, false]);
+        assert_eq!(diff.pdo_current, [true; 3]);
+        assert_eq!(diff.voltage_upper_limit, [true, false, true]);
+        assert_eq!(diff.voltage_lower_limit, [true; 3]);
+    }
+
+    #[test]
+    fn changed_sectors_of_pdo_change() {
+        let factory = NvmConfig::from_bytes(FACTORY_NVM);
+        let mut config = factory;
+        assert_eq!(config.changed_sectors(&factory), 0);
+        config.set_pdo_current(PdoChannel::PDO1, 300);
+        assert_eq!(config.changed_sectors(&factory), 0x08);
+        config.set_pdo_voltage(PdoChannel::PDO3, 180);
+        assert_eq!(config.changed_sectors(&factory), 0x18);
+    }
+}
@@ -1,22 +1,17 @@
 use bitfield::bitfield;
 
-#[derive(Debug, PartialOrd, PartialEq)]
+#[derive(Debug, Default, PartialOrd, PartialEq)]
 pub enum FastSwapSupport {
+    #[default]
     NotSupported = 0,
     DefaultUsb = 1,
     _1A5_5V = 2,
     _3A0_5V = 3
 }
 
-impl Default for FastSwapSupport {
-    fn default() -> Self {
-        FastSwapSupport::NotSupported
-    }
-}
-
-impl Into<u32> for FastSwapSupport {
-    fn into(self) -> u32 {
-        match self {
+impl From<FastSwapSupport> for u32 {
+    fn from(fss: FastSwapSupport) -> u32 {
+        match fss {
             FastSwapSupport::NotSupported => 0,
             FastSwapSupport::DefaultUsb => 1,
             FastSwapSupport::_1A5_5V => 2,
@@ -25,9 +20,21 @@ impl Into<u32> for FastSwapSupport {
     }
 }
 
+impl From<u32> for FastSwapSupport {
+    fn from(bits: u32) -> Self {
+        match bits & 0x3 {
+            1 => FastSwapSupport::DefaultUsb,
+            2 => FastSwapSupport::_1A5_5V,
+            3 => FastSwapSupport::_3A0_5V,
+            _ => FastSwapSupport::NotSupported,
+        }
+    }
+}
+
 const PDO_SNK_FIXED: u32 = 0x0 << 30;
 
 bitfield!{
+    #[derive(Clone, Copy, PartialEq)]
     pub struct FixedPdo(u32);
     impl Debug;
     // The fields default to u16
@@ -60,6 +67,7 @@ impl FixedPdo {
 
 const PDO_SNK_VARIABLE: u32 = 0x1 << 30;
 bitfield!{
+    #[derive(Clone, Copy, PartialEq)]
     pub struct VariablePdo(u32);
     impl Debug;
     // The fields default to u16
@@ -77,6 +85,7 @@ impl Default for VariablePdo {
 
 const PDO_SNK_BATTERY: u32 = 0x2 << 30;
 bitfield!{
+    #[derive(Clone, Copy, PartialEq)]
     pub struct BatteryPdo(u32);
     impl Debug;
     // The fields default to u16
@@ -91,6 +100,16 @@ impl Default for BatteryPdo {
     }
 }
 
+/// Swap the battery (01) and variable (10) type codes between the driver
+/// encoding and the USB PD spec, leaving fixed and augmented PDOs untouched
+fn swap_pdo_type(bits: u32) -> u32 {
+    match bits >> 30 {
+        0b01 | 0b10 => bits ^ 0xC000_0000,
+        _ => bits,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Pdo {
     Fixed(FixedPdo),
     Variable(VariablePdo),
@@ -102,6 +121,25 @@ impl Pdo {
         Pdo::Fixed(FixedPdo::new(voltage, current))
     }
 
+    /// Create a fixed PDO from mV and mA in a const context, truncated to the
+    /// 50mV/10mA steps of the PDO encoding
+    pub const fn fixed_const(voltage_mv: u16, current_ma: u16) -> Self {
+        let voltage = (voltage_mv as u32 / 50) & 0x3FF;
+        let current = (current_ma as u32 / 10) & 0x3FF;
+        Pdo::Fixed(FixedPdo(PDO_SNK_FIXED | voltage << 10 | current))
+    }
+
+    /// Create a fixed PDO from volts and amps, rounded to the nearest 50mV/10mA step.
+    /// The returned flag is true if rounding changed the requested value.
+    #[cfg(feature = "std")]
+    pub fn fixed_volts(volts: f32, amps: f32) -> (Self, bool) {
+        let voltage = volts * 20.0;
+        let current = amps * 100.0;
+        let rounded = (voltage - voltage.round()).abs() > 1e-3
+            || (current - current.round()).abs() > 1e-3;
+        (Pdo::new_fixed(voltage.round() as u16, current.round() as u16), rounded)
+    }
+
     pub fn dual_role_power(&mut self, dual: bool) -> &mut Self {
         if let Pdo::Fixed(x) = self {
             x.set_dual_role_power(dual);
@@ -137,6 +175,38 @@ impl Pdo {
         self
     }
 
+    /// Power of the PDO in mW, using the maximum voltage of variable PDOs
+    pub fn power_mw(&self) -> u32 {
+        match self {
+            Pdo::Fixed(a) => a.voltage() * 50 * a.current() * 10 / 1000,
+            Pdo::Variable(a) => a.max_voltage() * 50 * a.current() * 10 / 1000,
+            Pdo::Battery(a) => a.power() * 250,
+        }
+    }
+
+    /// Fast role swap current of a fixed PDO, `NotSupported` for other PDOs.
+    /// Informational only on the STUSB4500, which as a pure sink never
+    /// performs a fast role swap.
+    pub fn fast_role_swap(&self) -> FastSwapSupport {
+        match self {
+            Pdo::Fixed(x) => FastSwapSupport::from(x.fast_role_swap()),
+            _ => FastSwapSupport::NotSupported,
+        }
+    }
+
+    /// Raw 32-bit data object in the USB PD spec layout, for exchanging PDOs
+    /// with other PD libraries. Differs from `bits()` in the type field: the
+    /// driver encodes variable PDOs as 01 and battery PDOs as 10, the spec
+    /// the other way around. Fixed PDOs are identical.
+    pub fn as_pd_object(&self) -> u32 {
+        swap_pdo_type(self.bits())
+    }
+
+    /// Decode a raw USB PD data object, `None` for augmented PDOs
+    pub fn from_pd_object(object: u32) -> Option<Self> {
+        Self::from_bits(swap_pdo_type(object))
+    }
+
     pub(crate) fn bits(&self) -> u32 {
         match self {
             Pdo::Fixed(a) => a.0,
@@ -158,8 +228,65 @@ impl Pdo {
 
 #[cfg(test)]
 mod tests {
-    use crate::pdo::{Pdo};
+    use crate::pdo::{FastSwapSupport, Pdo};
 
+    #[test]
+    fn fast_role_swap_decode() {
+        assert_eq!(Pdo::new_fixed(100, 300).fast_role_swap(), FastSwapSupport::NotSupported);
+        let pdo = Pdo::from_bits(3 << 23 | 100 << 10 | 300).unwrap();
+        assert_eq!(pdo.fast_role_swap(), FastSwapSupport::_3A0_5V);
+        assert_eq!(u32::from(pdo.fast_role_swap()), 3);
+    }
 
+    #[test]
+    #[cfg(feature = "std")]
+    fn fixed_volts_exact() {
+        let (pdo, rounded) = Pdo::fixed_volts(9.0, 3.0);
+        assert!(!rounded);
+        assert_eq!(pdo.bits(), Pdo::new_fixed(180, 300).bits());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn fixed_volts_rounded() {
+        let (pdo, rounded) = Pdo::fixed_volts(9.02, 1.234);
+        assert!(rounded);
+        assert_eq!(pdo.bits(), Pdo::new_fixed(180, 123).bits());
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn pd_object_spec_examples() {
+        // Fixed 5V 3A
+        assert_eq!(Pdo::new_fixed(100, 300).as_pd_object(), 0x0001_912C);
+        // Fixed 9V 3A
+        let pdo = Pdo::from_pd_object(0x0002_D12C).unwrap();
+        assert_eq!(pdo, Pdo::new_fixed(180, 300));
+        // Variable 5-20V 3A, type 10
+        let variable = Pdo::from_pd_object(0x9901_912C).unwrap();
+        match variable {
+            Pdo::Variable(v) => {
+                assert_eq!((v.min_voltage(), v.max_voltage(), v.current()), (100, 400, 300));
+            },
+            _ => panic!("expected variable PDO"),
+        }
+        assert_eq!(variable.as_pd_object(), 0x9901_912C);
+        assert_eq!(variable.bits(), 0x5901_912C);
+        // Battery 5-20V 60W, type 01
+        let battery = Pdo::from_pd_object(0x5901_90F0).unwrap();
+        match battery {
+            Pdo::Battery(b) => assert_eq!((b.min_voltage(), b.max_voltage(), b.power()), (100, 400, 240)),
+            _ => panic!("expected battery PDO"),
+        }
+        assert_eq!(battery.as_pd_object(), 0x5901_90F0);
+        // Augmented PPS 3.3-11V 3A
+        assert_eq!(Pdo::from_pd_object(0xC06E_213C), None);
+        assert_eq!(pdo.as_pd_object(), 0x0002_D12C);
+    }
+
+    #[test]
+    fn fixed_const_matches_new_fixed() {
+        const PDO_9V: Pdo = Pdo::fixed_const(9000, 2000);
+        assert_eq!(PDO_9V.bits(), Pdo::new_fixed(180, 200).bits());
+        assert_eq!(Pdo::fixed_const(5025, 1505).bits(), Pdo::new_fixed(100, 150).bits());
+    }
+}
@@ -1,20 +1,146 @@
 use bitfield::bitfield;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::pdo::Pdo;
+use crate::registers::FromRegisters;
 
 bitfield!{
+  /// Request data object sent by the chip, read from `RDO_REG_STATUS`.
+  /// The STUSB4500 builds the request itself from the sink PDOs and the source
+  /// capabilities, the flags cannot be set by the host and are read only.
+  #[derive(Clone, Copy, PartialEq)]
   pub struct Rdo(u32);
   impl Debug;
   // The fields default to u16
   pub position, _: 30, 28;
+  /// Never set by the STUSB4500, which does not support give back
   pub give_back, _ : 27;
+  /// Set by the chip when no sink PDO could be satisfied by the source
   pub capability_mismatch, _: 26;
   pub usb_communication_capable, _: 25;
   pub no_usb_suspend, _: 24;
+  /// PD 3.0 only, always clear in PD 2.0 contracts
   pub unchunked_extended_messages, _: 23;
+  /// PD 3.1 only, set if the sink can enter Extended Power Range mode
+  pub epr_mode_capable, _: 22;
   pub operating_current, _: 19, 10;
   pub max_operating_current, _: 9, 0;
+  /// PD 3.0 programmable (PPS) requests only: output voltage in 20mV steps.
+  /// Overlaps `operating_current`, the STUSB4500 itself never sends PPS requests.
+  pub pps_output_voltage, _: 20, 9;
+  /// PD 3.0 programmable (PPS) requests only: operating current in 50mA steps
+  pub pps_operating_current, _: 6, 0;
+
+}
+
+impl Rdo {
+    /// Position (1-7) of the requested PDO in the source's capabilities list,
+    /// not the sink PDO slot. Same as `position`.
+    pub fn source_object_position(&self) -> u8 {
+        self.position() as u8
+    }
+
+    /// Look up the requested PDO in the source capabilities
+    pub fn source_pdo<'a>(&self, source_caps: &'a [Pdo]) -> Option<&'a Pdo> {
+        (self.source_object_position() as usize).checked_sub(1).and_then(|i| source_caps.get(i))
+    }
+}
+
+impl FromRegisters for Rdo {
+    const LEN: usize = 4;
+    fn from_registers(bytes: &[u8]) -> Self {
+        Rdo(LittleEndian::read_u32(bytes))
+    }
+}
 
+/// Power contract negotiated with the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contract {
+    pub rdo: Rdo,
+    /// Voltage of the selected source PDO in mV. `None` if it is not a fixed
+    /// PDO or the source capabilities are no longer held in the RX buffer.
+    pub voltage_mv: Option<u32>,
 }
 
+/// Serialized with currents in mA instead of the raw RDO
+#[cfg(feature = "serde")]
+impl serde::Serialize for Contract {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("Contract", 5)?;
+        s.serialize_field("position", &self.position())?;
+        s.serialize_field("voltage_mv", &self.voltage_mv)?;
+        s.serialize_field("operating_current_ma", &self.operating_current_ma())?;
+        s.serialize_field("max_current_ma", &self.max_current_ma())?;
+        s.serialize_field("capability_mismatch", &self.rdo.capability_mismatch())?;
+        s.end()
+    }
+}
+
+impl Contract {
+    /// Position (1-7) of the selected PDO in the source capabilities
+    pub fn position(&self) -> u8 {
+        self.rdo.position() as u8
+    }
+
+    pub fn operating_current_ma(&self) -> u32 {
+        self.rdo.operating_current() * 10
+    }
+
+    pub fn max_current_ma(&self) -> u32 {
+        self.rdo.max_operating_current() * 10
+    }
 
+    /// Check the contract voltage is within `tolerance_mv` of `expected_mv`,
+    /// false if the voltage is unknown
+    pub fn voltage_within(&self, expected_mv: u32, tolerance_mv: u32) -> bool {
+        self.voltage_mv.map_or(false, |mv| mv.abs_diff(expected_mv) <= tolerance_mv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pdo::Pdo;
+    use crate::rdo::Rdo;
 
+    #[test]
+    fn source_pdo_lookup() {
+        let caps = [Pdo::new_fixed(100, 300), Pdo::new_fixed(180, 300), Pdo::new_fixed(300, 300)];
+        let rdo = Rdo(3 << 28 | 300 << 10 | 300);
+        assert_eq!(rdo.source_object_position(), 3);
+        assert_eq!(rdo.source_pdo(&caps), Some(&Pdo::new_fixed(300, 300)));
+        assert_eq!(rdo.source_pdo(&caps[..2]), None);
+        assert_eq!(Rdo(0).source_pdo(&caps), None);
+    }
 
+    #[test]
+    fn request_flags_decode() {
+        let rdo = Rdo(1 << 28 | 1 << 26 | 50 << 10 | 300);
+        assert!(rdo.capability_mismatch());
+        assert!(!rdo.give_back());
+        let rdo = Rdo(3 << 28 | 1 << 27 | 300 << 10 | 300);
+        assert!(!rdo.capability_mismatch());
+        assert!(rdo.give_back());
+        assert_eq!(rdo.position(), 3);
+    }
+
+    #[test]
+    fn pd3_fields_decode() {
+        // Fixed request with unchunked extended messages and EPR mode capable
+        let rdo = Rdo(2 << 28 | 1 << 23 | 1 << 22 | 300 << 10 | 300);
+        assert!(rdo.unchunked_extended_messages());
+        assert!(rdo.epr_mode_capable());
+        assert_eq!(rdo.operating_current(), 300);
+        assert_eq!(rdo.max_operating_current(), 300);
+        // PPS request for 9.1V at 2A
+        let rdo = Rdo(4 << 28 | 1 << 23 | 455 << 9 | 40);
+        assert_eq!(rdo.position(), 4);
+        assert_eq!(rdo.pps_output_voltage(), 455);
+        assert_eq!(rdo.pps_operating_current(), 40);
+        assert!(!rdo.epr_mode_capable());
+        // PPS request for 21V uses bit 20 of the output voltage
+        let rdo = Rdo(4 << 28 | 1050 << 9 | 60);
+        assert_eq!(rdo.pps_output_voltage(), 1050);
+        assert_eq!(rdo.pps_operating_current(), 60);
+    }
+}
@@ -1,7 +1,12 @@
 #![allow(non_upper_case_globals)]
 
+use core::fmt;
+
+use bitfield::bitfield;
 use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Register {
     BcdTypeCRevL = 0x06,
     BcdTypeCRevH = 0x07,
@@ -38,19 +43,92 @@ pub enum Register {
     GpioSWGpio = 0x2D,
     //0x2E, Reserved
     DeviceId = 0x2F,
-    //0x30, Reserved
+    RXByteCnt = 0x30,
     RXHeaderL = 0x31,
     RXHeaderH = 0x32,
-    RXDataObj = 0x33,//4 bytes
+    RXDataObj = 0x33,//7 * 4 bytes
     TXHeaderL = 0x51,
     TXHeaderH = 0x52,
-    //0x53-0x6F, Reserved
+    RWBuffer = 0x53,//8 bytes
+    //0x5B-0x6F, Reserved
     DPMPDONumb = 0x70,
     //0x71-0x84, Reserved
     DPMSNKPDO1 = 0x85,//4 bytes
     DPMSNKPDO2 = 0x89,//4 bytes
     DPMSNKPDO3 = 0x8D,//4 bytes
     RDORegStatus = 0x91,//4 bytes
+    FTPCustPassword = 0x95,
+    FTPCtrl0 = 0x96,
+    FTPCtrl1 = 0x97,
+}
+
+/// All registers with their datasheet names, in address order
+pub const REGISTER_MAP: &[(Register, &str)] = &[
+    (Register::BcdTypeCRevL, "BCD_TYPEC_REV_LOW"),
+    (Register::BcdTypeCRevH, "BCD_TYPEC_REV_HIGH"),
+    (Register::BcdUsbPDRevL, "BCD_USBPD_REV_LOW"),
+    (Register::BcdUsbPDRevH, "BCD_USBPD_REV_HIGH"),
+    (Register::DeviceCapabHigh, "DEVICE_CAPAB_HIGH"),
+    (Register::AlertStatus1, "ALERT_STATUS_1"),
+    (Register::AlertStatus1Mask, "ALERT_STATUS_1_MASK"),
+    (Register::PortStatus0, "PORT_STATUS_0"),
+    (Register::PortStatus1, "PORT_STATUS_1"),
+    (Register::TypeCMonitoringStatus0, "TYPEC_MONITORING_STATUS_0"),
+    (Register::TypeCMonitoringStatus1, "TYPEC_MONITORING_STATUS_1"),
+    (Register::CCStatus, "CC_STATUS"),
+    (Register::CCHWFaultStatus0, "CC_HW_FAULT_STATUS_0"),
+    (Register::CCHWFaultStatus1, "CC_HW_FAULT_STATUS_1"),
+    (Register::PDTypeCStatus, "PD_TYPEC_STATUS"),
+    (Register::TypeCStatus, "TYPEC_STATUS"),
+    (Register::PRTStatus, "PRT_STATUS"),
+    (Register::PDCommandCtrl, "PD_COMMAND_CTRL"),
+    (Register::MonitoringCtrl0, "MONITORING_CTRL_0"),
+    (Register::MonitoringCtrl2, "MONITORING_CTRL_2"),
+    (Register::ResetCtrl, "RESET_CTRL"),
+    (Register::VbusDischargeTimeCtrl, "VBUS_DISCHARGE_TIME_CTRL"),
+    (Register::VbusDischargeCtrl, "VBUS_DISCHARGE_CTRL"),
+    (Register::VbusCtrl, "VBUS_CTRL"),
+    (Register::PEFSM, "PE_FSM"),
+    (Register::GpioSWGpio, "GPIO_SW_GPIO"),
+    (Register::DeviceId, "DEVICE_ID"),
+    (Register::RXByteCnt, "RX_BYTE_CNT"),
+    (Register::RXHeaderL, "RX_HEADER_LOW"),
+    (Register::RXHeaderH, "RX_HEADER_HIGH"),
+    (Register::RXDataObj, "RX_DATA_OBJ"),
+    (Register::TXHeaderL, "TX_HEADER_LOW"),
+    (Register::TXHeaderH, "TX_HEADER_HIGH"),
+    (Register::RWBuffer, "RW_BUFFER"),
+    (Register::DPMPDONumb, "DPM_PDO_NUMB"),
+    (Register::DPMSNKPDO1, "DPM_SNK_PDO1"),
+    (Register::DPMSNKPDO2, "DPM_SNK_PDO2"),
+    (Register::DPMSNKPDO3, "DPM_SNK_PDO3"),
+    (Register::RDORegStatus, "RDO_REG_STATUS"),
+    (Register::FTPCustPassword, "FTP_CUST_PASSWORD"),
+    (Register::FTPCtrl0, "FTP_CTRL_0"),
+    (Register::FTPCtrl1, "FTP_CTRL_1"),
+];
+
+impl Register {
+    /// Datasheet name of the register
+    pub fn name(&self) -> &'static str {
+        REGISTER_MAP.iter()
+            .find(|(reg, _)| reg == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("")
+    }
+
+    /// Register address
+    pub fn addr(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Types decoded from a block of contiguous registers, read with `read_into`
+pub trait FromRegisters: Sized {
+    /// Number of registers (bytes) in the block, at most 8
+    const LEN: usize;
+    /// Decode the block, `bytes` holds exactly `LEN` bytes
+    fn from_registers(bytes: &[u8]) -> Self;
 }
 
 bitflags! {
@@ -86,9 +164,695 @@ bitflags! {
     }
 }
 
+/// Single alert source, as yielded by `Alert::iter_set`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertKind {
+    PortStatus,
+    TypeCMonitoringStatus,
+    CCHWFaultStatus,
+    PDTypeCStatus,
+    PRTStatus,
+}
+
+const ALERT_KINDS: [(Alert, AlertKind); 5] = [
+    (Alert::PortStatus, AlertKind::PortStatus),
+    (Alert::TypeCMonitoringStatus, AlertKind::TypeCMonitoringStatus),
+    (Alert::CCHWFaultStatus, AlertKind::CCHWFaultStatus),
+    (Alert::PDTypeCStatus, AlertKind::PDTypeCStatus),
+    (Alert::PRTStatus, AlertKind::PRTStatus),
+];
+
 impl Alert {
     pub(crate) fn from_masked_bits(bits: u8) -> Alert {
         // Mask to ignore reserved/undocumented bits
         Self::from_bits(bits & Self::_Mask.bits).unwrap()
     }
-}
\ No newline at end of file
+
+    /// Iterate over the asserted alerts, highest bit first
+    pub fn iter_set(&self) -> impl Iterator<Item = AlertKind> {
+        let alert = *self;
+        ALERT_KINDS.iter().filter(move |(flag, _)| alert.contains(*flag)).map(|(_, kind)| *kind)
+    }
+}
+
+bitflags! {
+    pub struct MonitoringStatus: u8 {
+        /// `VBUS_READY`, bit 3 of `TYPEC_MONITORING_STATUS_1`, VBUS at the contract voltage
+        const VbusReady             = 0b0000_1000;
+        /// `VBUS_VSAFE0V`, bit 2 of `TYPEC_MONITORING_STATUS_1`, VBUS below 0.8V
+        const Vsafe0V               = 0b0000_0100;
+        /// `VBUS_VALID_SNK`, bit 1 of `TYPEC_MONITORING_STATUS_1`, VBUS above the sink valid threshold
+        const VbusValidSnk          = 0b0000_0010;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MonitoringStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("MonitoringStatus", 3)?;
+        s.serialize_field("vbus_valid_snk", &self.contains(MonitoringStatus::VbusValidSnk))?;
+        s.serialize_field("vsafe0v", &self.contains(MonitoringStatus::Vsafe0V))?;
+        s.serialize_field("vbus_ready", &self.contains(MonitoringStatus::VbusReady))?;
+        s.end()
+    }
+}
+
+/// VBUS relative to the monitoring window of the contract
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VbusBand {
+    Vsafe0v,
+    BelowLow,
+    InRange,
+    AboveHigh,
+}
+
+impl VbusBand {
+    /// Decode `TYPEC_MONITORING_STATUS_0` and `TYPEC_MONITORING_STATUS_1`
+    pub fn from_registers(status0: u8, status1: u8) -> Self {
+        if MonitoringStatus::from_bits_truncate(status1).contains(MonitoringStatus::Vsafe0V) {
+            VbusBand::Vsafe0v
+        } else if status0 & 0x20 != 0 {
+            VbusBand::AboveHigh
+        } else if status0 & 0x10 != 0 {
+            VbusBand::BelowLow
+        } else {
+            VbusBand::InRange
+        }
+    }
+}
+
+/// Policy engine state read from `PE_FSM`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyEngineState {
+    Init,
+    SoftReset,
+    HardReset,
+    SendSoftReset,
+    Bist,
+    SnkStartup,
+    SnkDiscovery,
+    SnkWaitForCapabilities,
+    SnkEvaluateCapabilities,
+    SnkSelectCapabilities,
+    SnkTransitionSink,
+    SnkReady,
+    SnkReadySending,
+    HardResetShutdown,
+    HardResetRecovery,
+    ErrorRecovery,
+    Unknown(u8),
+}
+
+impl From<u8> for PolicyEngineState {
+    fn from(state: u8) -> Self {
+        match state {
+            0x00 => PolicyEngineState::Init,
+            0x01 => PolicyEngineState::SoftReset,
+            0x02 => PolicyEngineState::HardReset,
+            0x03 => PolicyEngineState::SendSoftReset,
+            0x04 => PolicyEngineState::Bist,
+            0x12 => PolicyEngineState::SnkStartup,
+            0x13 => PolicyEngineState::SnkDiscovery,
+            0x14 => PolicyEngineState::SnkWaitForCapabilities,
+            0x15 => PolicyEngineState::SnkEvaluateCapabilities,
+            0x16 => PolicyEngineState::SnkSelectCapabilities,
+            0x17 => PolicyEngineState::SnkTransitionSink,
+            0x18 => PolicyEngineState::SnkReady,
+            0x19 => PolicyEngineState::SnkReadySending,
+            0x3A => PolicyEngineState::HardResetShutdown,
+            0x3B => PolicyEngineState::HardResetRecovery,
+            0x40 => PolicyEngineState::ErrorRecovery,
+            x => PolicyEngineState::Unknown(x),
+        }
+    }
+}
+
+/// Hard reset handshake reported in `PD_TYPEC_HAND_CHECK`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PdTypeCHandshake {
+    Clear,
+    HardResetCompleteAck,
+    HardResetReceivedAck,
+    HardResetSendAck,
+    Unknown(u8),
+}
+
+impl From<u8> for PdTypeCHandshake {
+    fn from(value: u8) -> Self {
+        match value & 0x0F {
+            0x0 => PdTypeCHandshake::Clear,
+            0x8 => PdTypeCHandshake::HardResetCompleteAck,
+            0xE => PdTypeCHandshake::HardResetReceivedAck,
+            0xF => PdTypeCHandshake::HardResetSendAck,
+            x => PdTypeCHandshake::Unknown(x),
+        }
+    }
+}
+
+/// Decoded `PD_TYPEC_STATUS`. Only bits 3:0 (`PD_TYPEC_HAND_CHECK`) are
+/// documented, the Type-C attachment is in `TYPEC_STATUS` and `PORT_STATUS_1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdTypeCStatus {
+    pub handshake: PdTypeCHandshake,
+}
+
+impl FromRegisters for PdTypeCStatus {
+    const LEN: usize = 1;
+    fn from_registers(bytes: &[u8]) -> Self {
+        PdTypeCStatus { handshake: PdTypeCHandshake::from(bytes[0]) }
+    }
+}
+
+/// Type-C current advertised by the source through its Rp pull-up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceCurrentAdvertisement {
+    /// Default USB current (500mA/900mA)
+    Default,
+    /// 1.5A at 5V
+    Current1A5,
+    /// 3.0A at 5V
+    Current3A0,
+}
+
+impl SourceCurrentAdvertisement {
+    /// Current in mA a sink may draw, taking default USB power as 500mA
+    pub fn current_ma(self) -> u32 {
+        match self {
+            SourceCurrentAdvertisement::Default => 500,
+            SourceCurrentAdvertisement::Current1A5 => 1500,
+            SourceCurrentAdvertisement::Current3A0 => 3000,
+        }
+    }
+}
+
+/// Raw state of a CC line, as the Rp pull-up seen on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CcState {
+    Open,
+    Default,
+    Power1_5,
+    Power3_0,
+}
+
+impl From<u8> for CcState {
+    fn from(state: u8) -> Self {
+        match state & 0x03 {
+            1 => CcState::Default,
+            2 => CcState::Power1_5,
+            3 => CcState::Power3_0,
+            _ => CcState::Open,
+        }
+    }
+}
+
+bitfield!{
+    pub struct CcStatus(u8);
+    impl Debug;
+    u8;
+    pub cc1_state, _: 1, 0;
+    pub cc2_state, _: 3, 2;
+    pub connect_result, _: 4;
+    pub looking_for_connection, _: 5;
+}
+
+impl FromRegisters for CcStatus {
+    const LEN: usize = 1;
+    fn from_registers(bytes: &[u8]) -> Self {
+        CcStatus(bytes[0])
+    }
+}
+
+impl CcStatus {
+    /// States of CC1 and CC2
+    pub fn line_states(&self) -> (CcState, CcState) {
+        (CcState::from(self.cc1_state()), CcState::from(self.cc2_state()))
+    }
+
+    /// Current advertised on the connected CC line, `None` if no Rp is detected
+    pub fn source_current(&self) -> Option<SourceCurrentAdvertisement> {
+        let state = if self.cc1_state() != 0 { self.cc1_state() } else { self.cc2_state() };
+        match state {
+            1 => Some(SourceCurrentAdvertisement::Default),
+            2 => Some(SourceCurrentAdvertisement::Current1A5),
+            3 => Some(SourceCurrentAdvertisement::Current3A0),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    pub struct PrtStatus: u8 {
+        const BistReceived          = 0b0001_0000;
+        const MsgReceived           = 0b0000_0100;
+        const HwResetReceived       = 0b0000_0001;
+    }
+}
+
+/// State of the Type-C connection state machine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeCFsmState {
+    UnattachedSnk,
+    AttachWaitSnk,
+    AttachedSnk,
+    DebugAccessorySnk,
+    TrySrc,
+    UnattachedAccessory,
+    AttachWaitAccessory,
+    ErrorRecovery,
+    Unknown(u8),
+}
+
+impl From<u8> for TypeCFsmState {
+    fn from(state: u8) -> Self {
+        match state {
+            0x00 => TypeCFsmState::UnattachedSnk,
+            0x01 => TypeCFsmState::AttachWaitSnk,
+            0x02 => TypeCFsmState::AttachedSnk,
+            0x03 => TypeCFsmState::DebugAccessorySnk,
+            0x0C => TypeCFsmState::TrySrc,
+            0x0D => TypeCFsmState::UnattachedAccessory,
+            0x0E => TypeCFsmState::AttachWaitAccessory,
+            0x13 => TypeCFsmState::ErrorRecovery,
+            x => TypeCFsmState::Unknown(x),
+        }
+    }
+}
+
+impl fmt::Display for TypeCFsmState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeCFsmState::UnattachedSnk => f.write_str("Unattached.SNK"),
+            TypeCFsmState::AttachWaitSnk => f.write_str("AttachWait.SNK"),
+            TypeCFsmState::AttachedSnk => f.write_str("Attached.SNK"),
+            TypeCFsmState::DebugAccessorySnk => f.write_str("DebugAccessory.SNK"),
+            TypeCFsmState::TrySrc => f.write_str("Try.SRC"),
+            TypeCFsmState::UnattachedAccessory => f.write_str("Unattached.Accessory"),
+            TypeCFsmState::AttachWaitAccessory => f.write_str("AttachWait.Accessory"),
+            TypeCFsmState::ErrorRecovery => f.write_str("ErrorRecovery"),
+            TypeCFsmState::Unknown(x) => write!(f, "Unknown({:#04x})", x),
+        }
+    }
+}
+
+bitfield!{
+    #[derive(Clone, Copy, PartialEq)]
+    pub struct TypeCStatus(u8);
+    impl Debug;
+    u8;
+    pub fsm_state_raw, _: 4, 0;
+    pub reverse, _: 7;
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TypeCStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("TypeCStatus", 2)?;
+        s.serialize_field("fsm_state", &self.fsm_state_raw())?;
+        s.serialize_field("reverse", &self.reverse())?;
+        s.end()
+    }
+}
+
+impl FromRegisters for TypeCStatus {
+    const LEN: usize = 1;
+    fn from_registers(bytes: &[u8]) -> Self {
+        TypeCStatus(bytes[0])
+    }
+}
+
+impl TypeCStatus {
+    pub fn fsm_state(&self) -> TypeCFsmState {
+        TypeCFsmState::from(self.fsm_state_raw())
+    }
+}
+
+/// PD control messages the sink can send through `send_pd_command`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PdControlMessage {
+    GetSourceCap = 0x07,
+    SoftReset = 0x0D,
+}
+
+/// Message type of a Source_Capabilities data message
+pub const PD_DATA_SOURCE_CAPABILITIES: u16 = 0x01;
+
+bitfield!{
+    /// USB PD message header
+    #[derive(Clone, Copy, PartialEq)]
+    pub struct MessageHeader(u16);
+    impl Debug;
+    u16;
+    pub message_type, _: 4, 0;
+    pub port_data_role, _: 5;
+    pub u8, spec_revision_raw, _: 7, 6;
+    pub port_power_role, _: 8;
+    pub u8, message_id, _: 11, 9;
+    pub u8, num_data_objects, _: 14, 12;
+    pub extended, _: 15;
+}
+
+bitflags! {
+    /// Fault conditions aggregated from the fault and monitoring registers
+    pub struct FaultFlags: u8 {
+        /// `VBUS_DISCH_FAULT` in `CC_HW_FAULT_STATUS_1`
+        const VbusDischargeFault    = 0b0000_0001;
+        /// `VPU_OVP_FAULT` in `CC_HW_FAULT_STATUS_1`, over-voltage on the CC pins
+        const CCOverVoltage         = 0b0000_0010;
+        /// `VBUS_HIGH_STATUS` in `TYPEC_MONITORING_STATUS_0`, VBUS above the valid window
+        const VbusOverVoltage       = 0b0000_0100;
+        /// `VBUS_LOW_STATUS` in `TYPEC_MONITORING_STATUS_0`, VBUS below the valid window
+        const VbusUnderVoltage      = 0b0000_1000;
+    }
+}
+
+/// Power role of the sender of a SOP message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerRole {
+    Sink,
+    Source,
+}
+
+/// Data role of the sender of a SOP message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataRole {
+    Ufp,
+    Dfp,
+}
+
+/// Decoded PD message type. Control messages have no data objects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageType {
+    GoodCrc,
+    GotoMin,
+    Accept,
+    Reject,
+    Ping,
+    PsRdy,
+    GetSourceCap,
+    GetSinkCap,
+    DrSwap,
+    PrSwap,
+    VconnSwap,
+    Wait,
+    SoftReset,
+    NotSupported,
+    SourceCapabilities,
+    Request,
+    Bist,
+    SinkCapabilities,
+    Alert,
+    VendorDefined,
+    /// Other control message, by its raw type
+    Control(u8),
+    /// Other data message, by its raw type
+    Data(u8),
+    /// Extended message, by its raw type
+    Extended(u8),
+}
+
+impl FromRegisters for MessageHeader {
+    const LEN: usize = 2;
+    fn from_registers(bytes: &[u8]) -> Self {
+        MessageHeader(LittleEndian::read_u16(bytes))
+    }
+}
+
+impl MessageHeader {
+    /// Major PD spec revision of the sender (1-3), `None` for the reserved value
+    pub fn spec_revision(&self) -> Option<u8> {
+        match self.spec_revision_raw() {
+            0b11 => None,
+            raw => Some(raw + 1),
+        }
+    }
+
+    /// Power role of the sender, the cable plug bit for SOP'/SOP'' messages
+    pub fn power_role(&self) -> PowerRole {
+        if self.port_power_role() { PowerRole::Source } else { PowerRole::Sink }
+    }
+
+    pub fn data_role(&self) -> DataRole {
+        if self.port_data_role() { DataRole::Dfp } else { DataRole::Ufp }
+    }
+
+    /// Message type decoded by whether it is a control, data or extended message
+    pub fn kind(&self) -> MessageType {
+        let raw = self.message_type() as u8;
+        if self.extended() {
+            return MessageType::Extended(raw);
+        }
+        if self.num_data_objects() == 0 {
+            match raw {
+                0x01 => MessageType::GoodCrc,
+                0x02 => MessageType::GotoMin,
+                0x03 => MessageType::Accept,
+                0x04 => MessageType::Reject,
+                0x05 => MessageType::Ping,
+                0x06 => MessageType::PsRdy,
+                0x07 => MessageType::GetSourceCap,
+                0x08 => MessageType::GetSinkCap,
+                0x09 => MessageType::DrSwap,
+                0x0A => MessageType::PrSwap,
+                0x0B => MessageType::VconnSwap,
+                0x0C => MessageType::Wait,
+                0x0D => MessageType::SoftReset,
+                0x10 => MessageType::NotSupported,
+                x => MessageType::Control(x),
+            }
+        } else {
+            match raw {
+                0x01 => MessageType::SourceCapabilities,
+                0x02 => MessageType::Request,
+                0x03 => MessageType::Bist,
+                0x04 => MessageType::SinkCapabilities,
+                0x06 => MessageType::Alert,
+                0x0F => MessageType::VendorDefined,
+                x => MessageType::Data(x),
+            }
+        }
+    }
+
+    /// Check if this is the header of a Source_Capabilities message
+    pub fn is_source_capabilities(&self) -> bool {
+        self.message_type() == PD_DATA_SOURCE_CAPABILITIES && !self.extended() && self.num_data_objects() > 0
+    }
+}
+
+/// Type of device attached to the port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachedDevice {
+    None,
+    Sink,
+    Source,
+    DebugAccessory,
+    AudioAccessory,
+    PoweredCable,
+    Unknown(u8),
+}
+
+impl From<u8> for AttachedDevice {
+    fn from(device: u8) -> Self {
+        match device {
+            0b000 => AttachedDevice::None,
+            0b001 => AttachedDevice::Sink,
+            0b010 => AttachedDevice::Source,
+            0b011 => AttachedDevice::DebugAccessory,
+            0b100 => AttachedDevice::AudioAccessory,
+            0b101 => AttachedDevice::PoweredCable,
+            x => AttachedDevice::Unknown(x),
+        }
+    }
+}
+
+bitfield!{
+    /// `PORT_STATUS_1` register
+    #[derive(Clone, Copy, PartialEq)]
+    pub struct PortStatus(u8);
+    impl Debug;
+    u8;
+    pub attached, _: 0;
+    pub data_mode, _: 2;
+    pub power_mode, _: 3;
+    pub attached_device_raw, _: 7, 5;
+}
+
+impl FromRegisters for PortStatus {
+    const LEN: usize = 1;
+    fn from_registers(bytes: &[u8]) -> Self {
+        PortStatus(bytes[0])
+    }
+}
+
+/// Power role of the port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperatingMode {
+    Sink,
+    Source,
+    /// Not attached, no power role
+    Unknown,
+}
+
+impl PortStatus {
+    pub fn attached_device(&self) -> AttachedDevice {
+        AttachedDevice::from(self.attached_device_raw())
+    }
+
+    /// Power role from the `POWER_MODE` bit, `Unknown` while detached
+    pub fn operating_mode(&self) -> OperatingMode {
+        match (self.attached(), self.power_mode()) {
+            (false, _) => OperatingMode::Unknown,
+            (true, false) => OperatingMode::Sink,
+            (true, true) => OperatingMode::Source,
+        }
+    }
+}
+
+bitflags! {
+    /// `PORT_STATUS_0` in the low byte and `PORT_STATUS_1` in the high byte
+    pub struct PortStatusFlags: u16 {
+        const PowerMode             = 0b0000_1000_0000_0000;
+        const DataMode              = 0b0000_0100_0000_0000;
+        const Attach                = 0b0000_0001_0000_0000;
+        const AttachTrans           = 0b0000_0000_0000_0001;
+    }
+}
+
+impl FromRegisters for PortStatusFlags {
+    const LEN: usize = 2;
+    fn from_registers(bytes: &[u8]) -> Self {
+        Self::from_bits_truncate(LittleEndian::read_u16(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registers::*;
+
+    #[test]
+    fn cc_status_source_current() {
+        assert_eq!(CcStatus(0b0000_0000).source_current(), None);
+        assert_eq!(CcStatus(0b0000_0001).source_current(), Some(SourceCurrentAdvertisement::Default));
+        assert_eq!(CcStatus(0b0000_1000).source_current(), Some(SourceCurrentAdvertisement::Current1A5));
+        assert_eq!(CcStatus(0b0000_0011).source_current(), Some(SourceCurrentAdvertisement::Current3A0));
+        assert_eq!(CcStatus(0b0000_1100).source_current(), Some(SourceCurrentAdvertisement::Current3A0));
+    }
+
+    #[test]
+    fn cc_line_states() {
+        assert_eq!(CcStatus(0x00).line_states(), (CcState::Open, CcState::Open));
+        assert_eq!(CcStatus(0x01).line_states(), (CcState::Default, CcState::Open));
+        assert_eq!(CcStatus(0x08).line_states(), (CcState::Open, CcState::Power1_5));
+        assert_eq!(CcStatus(0x3F).line_states(), (CcState::Power3_0, CcState::Power3_0));
+    }
+
+    #[test]
+    fn typec_fsm_state_decode() {
+        assert_eq!(TypeCStatus(0x02).fsm_state(), TypeCFsmState::AttachedSnk);
+        assert_eq!(TypeCStatus(0x81).fsm_state(), TypeCFsmState::AttachWaitSnk);
+        assert!(TypeCStatus(0x81).reverse());
+        assert_eq!(TypeCStatus(0x13).fsm_state(), TypeCFsmState::ErrorRecovery);
+        assert_eq!(TypeCStatus(0x1F).fsm_state(), TypeCFsmState::Unknown(0x1F));
+    }
+
+    #[test]
+    fn port_status_attached_device() {
+        assert_eq!(PortStatus(0x00).attached_device(), AttachedDevice::None);
+        assert_eq!(PortStatus(0x41).attached_device(), AttachedDevice::Source);
+        assert!(PortStatus(0x41).attached());
+        assert_eq!(PortStatus(0x61).attached_device(), AttachedDevice::DebugAccessory);
+        assert_eq!(PortStatus(0xE0).attached_device(), AttachedDevice::Unknown(7));
+    }
+
+    #[test]
+    fn register_map_entries() {
+        assert_eq!(REGISTER_MAP[0], (Register::BcdTypeCRevL, "BCD_TYPEC_REV_LOW"));
+        assert_eq!(REGISTER_MAP[0].0.addr(), 0x06);
+        assert_eq!(Register::DeviceId.name(), "DEVICE_ID");
+        assert_eq!(Register::DeviceId.addr(), 0x2F);
+        assert_eq!(Register::RDORegStatus.name(), "RDO_REG_STATUS");
+        assert!(REGISTER_MAP.windows(2).all(|w| w[0].0.addr() < w[1].0.addr()));
+    }
+
+    #[test]
+    fn port_status_flags_membership() {
+        let flags = PortStatusFlags::from_registers(&[0x01, 0x49]);
+        assert!(flags.contains(PortStatusFlags::Attach | PortStatusFlags::AttachTrans));
+        assert!(flags.contains(PortStatusFlags::PowerMode));
+        assert!(!flags.contains(PortStatusFlags::DataMode));
+        // Attached device bits are not flags
+        assert_eq!(PortStatusFlags::from_registers(&[0x00, 0xE0]), PortStatusFlags::empty());
+    }
+
+    #[test]
+    fn alert_iter_set() {
+        let alert = Alert::from_masked_bits(0xFA);
+        let mut kinds = alert.iter_set();
+        assert_eq!(kinds.next(), Some(AlertKind::PortStatus));
+        assert_eq!(kinds.next(), Some(AlertKind::TypeCMonitoringStatus));
+        assert_eq!(kinds.next(), Some(AlertKind::CCHWFaultStatus));
+        assert_eq!(kinds.next(), Some(AlertKind::PDTypeCStatus));
+        assert_eq!(kinds.next(), Some(AlertKind::PRTStatus));
+        assert_eq!(kinds.next(), None);
+        let mut kinds = Alert::from_masked_bits(0x22).iter_set();
+        assert_eq!(kinds.next(), Some(AlertKind::TypeCMonitoringStatus));
+        assert_eq!(kinds.next(), Some(AlertKind::PRTStatus));
+        assert_eq!(kinds.next(), None);
+        assert_eq!(Alert::empty().iter_set().count(), 0);
+    }
+
+    #[test]
+    fn vbus_band_decode() {
+        assert_eq!(VbusBand::from_registers(0x00, 0x04), VbusBand::Vsafe0v);
+        assert_eq!(VbusBand::from_registers(0x10, 0x04), VbusBand::Vsafe0v);
+        assert_eq!(VbusBand::from_registers(0x10, 0x02), VbusBand::BelowLow);
+        assert_eq!(VbusBand::from_registers(0x00, 0x0A), VbusBand::InRange);
+        assert_eq!(VbusBand::from_registers(0x20, 0x0A), VbusBand::AboveHigh);
+        // Transition latches do not move the band
+        assert_eq!(VbusBand::from_registers(0x06, 0x0A), VbusBand::InRange);
+    }
+
+    #[test]
+    fn message_header_spec_revision() {
+        // Source_Capabilities with 2 objects, PD 2.0 and PD 3.0
+        assert_eq!(MessageHeader(0x2041).spec_revision(), Some(2));
+        assert_eq!(MessageHeader(0x2081).spec_revision(), Some(3));
+        assert_eq!(MessageHeader(0x2001).spec_revision(), Some(1));
+        assert_eq!(MessageHeader(0x20C1).spec_revision(), None);
+    }
+
+    #[test]
+    fn port_status_operating_mode() {
+        assert_eq!(PortStatus(0x41).operating_mode(), OperatingMode::Sink);
+        assert_eq!(PortStatus(0x45).operating_mode(), OperatingMode::Sink);
+        assert_eq!(PortStatus(0x49).operating_mode(), OperatingMode::Source);
+        assert_eq!(PortStatus(0x00).operating_mode(), OperatingMode::Unknown);
+    }
+
+    #[test]
+    fn pd_typec_status_decode() {
+        let status = PdTypeCStatus::from_registers(&[0xF8]);
+        assert_eq!(status.handshake, PdTypeCHandshake::HardResetCompleteAck);
+        assert_eq!(PdTypeCStatus::from_registers(&[0x0E]).handshake, PdTypeCHandshake::HardResetReceivedAck);
+        assert_eq!(PdTypeCStatus::from_registers(&[0x03]).handshake, PdTypeCHandshake::Unknown(3));
+    }
+
+    #[test]
+    fn message_header_fields() {
+        // Source_Capabilities with 5 PDOs from a PD 3.0 source
+        let caps = MessageHeader(0x51A1);
+        assert_eq!(caps.kind(), MessageType::SourceCapabilities);
+        assert_eq!((caps.power_role(), caps.data_role()), (PowerRole::Source, DataRole::Dfp));
+        assert_eq!((caps.spec_revision(), caps.message_id(), caps.num_data_objects()), (Some(3), 0, 5));
+        // Accept and PS_RDY from a PD 2.0 source
+        let accept = MessageHeader(0x0363);
+        assert_eq!((accept.kind(), accept.message_id(), accept.spec_revision()), (MessageType::Accept, 1, Some(2)));
+        assert_eq!((MessageHeader(0x0566).kind(), MessageHeader(0x0566).message_id()), (MessageType::PsRdy, 2));
+        // Request sent by the sink
+        let request = MessageHeader(0x1042);
+        assert_eq!(request.kind(), MessageType::Request);
+        assert_eq!((request.power_role(), request.data_role()), (PowerRole::Sink, DataRole::Ufp));
+        assert_eq!(MessageHeader(0x8001).kind(), MessageType::Extended(1));
+    }
+}
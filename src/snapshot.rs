@@ -0,0 +1,131 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::pdo::Pdo;
+use crate::rdo::Contract;
+use crate::registers::{Alert, MonitoringStatus, PrtStatus, TypeCStatus};
+
+/// State of the chip read in one go
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceSnapshot {
+    pub attached: bool,
+    pub typec: TypeCStatus,
+    pub monitoring: MonitoringStatus,
+    /// Negotiated contract, `None` without an explicit PD contract
+    pub contract: Option<Contract>,
+}
+
+impl DeviceSnapshot {
+    /// Compact one-line summary, e.g. `ATTACHED CC2 9000mV@2000mA (PDO2) VBUS_OK`
+    pub fn summary(&self) -> String<128> {
+        let mut s = String::new();
+        // Cannot overflow, the longest summary is well below the capacity
+        let _ = self.write_summary(&mut s);
+        s
+    }
+
+    fn write_summary(&self, s: &mut String<128>) -> core::fmt::Result {
+        if self.attached {
+            s.write_str("ATTACHED")?;
+            s.write_str(if self.typec.reverse() { " CC2" } else { " CC1" })?;
+        } else {
+            s.write_str("DETACHED")?;
+        }
+        match &self.contract {
+            Some(contract) => {
+                match contract.voltage_mv {
+                    Some(mv) => write!(s, " {}mV", mv)?,
+                    None => s.write_str(" ?mV")?,
+                }
+                write!(s, "@{}mA (PDO{})", contract.operating_current_ma(), contract.position())?;
+            },
+            None => s.write_str(" NO_CONTRACT")?,
+        }
+        if self.monitoring.contains(MonitoringStatus::VbusValidSnk) {
+            s.write_str(" VBUS_OK")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sink PDO configuration stored in NVM next to the live one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdoConfigReport {
+    pub nvm_num_pdo: u8,
+    pub live_num_pdo: u8,
+    /// Fixed PDOs decoded from the NVM voltage and current fields
+    pub nvm_pdos: [Pdo; 3],
+    pub live_pdos: [Pdo; 3],
+    /// True if the counts and the voltage and current of all PDOs match
+    pub consistent: bool,
+}
+
+/// Status registers read in response to an interrupt.
+/// Only the registers flagged by the alert are read, the others are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterruptSnapshot {
+    pub alert: Alert,
+    /// `PORT_STATUS_0` and `PORT_STATUS_1`
+    pub port_status: Option<[u8; 2]>,
+    /// `TYPEC_MONITORING_STATUS_0` and `TYPEC_MONITORING_STATUS_1`
+    pub monitoring_status: Option<[u8; 2]>,
+    /// `CC_HW_FAULT_STATUS_0` and `CC_HW_FAULT_STATUS_1`
+    pub cc_hw_fault_status: Option<[u8; 2]>,
+    /// `PD_TYPEC_STATUS`
+    pub pd_typec_status: Option<u8>,
+    pub prt_status: Option<PrtStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rdo::{Contract, Rdo};
+    use crate::registers::{MonitoringStatus, TypeCStatus};
+    use crate::snapshot::DeviceSnapshot;
+
+    #[test]
+    fn summary_attached_with_contract() {
+        let snapshot = DeviceSnapshot {
+            attached: true,
+            typec: TypeCStatus(0x82),
+            monitoring: MonitoringStatus::VbusValidSnk | MonitoringStatus::VbusReady,
+            contract: Some(Contract {
+                rdo: Rdo(2 << 28 | 200 << 10 | 200),
+                voltage_mv: Some(9000),
+            }),
+        };
+        assert_eq!(snapshot.summary().as_str(), "ATTACHED CC2 9000mV@2000mA (PDO2) VBUS_OK");
+    }
+
+    #[test]
+    fn summary_detached() {
+        let snapshot = DeviceSnapshot {
+            attached: false,
+            typec: TypeCStatus(0x00),
+            monitoring: MonitoringStatus::empty(),
+            contract: None,
+        };
+        assert_eq!(snapshot.summary().as_str(), "DETACHED NO_CONTRACT");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serialize_json() {
+        let snapshot = DeviceSnapshot {
+            attached: true,
+            typec: TypeCStatus(0x82),
+            monitoring: MonitoringStatus::VbusValidSnk | MonitoringStatus::VbusReady,
+            contract: Some(Contract {
+                rdo: Rdo(2 << 28 | 200 << 10 | 300),
+                voltage_mv: Some(9000),
+            }),
+        };
+        assert_eq!(serde_json::to_string(&snapshot).unwrap(), concat!(
+            r#"{"attached":true,"typec":{"fsm_state":2,"reverse":true},"#,
+            r#""monitoring":{"vbus_valid_snk":true,"vsafe0v":false,"vbus_ready":true},"#,
+            r#""contract":{"position":2,"voltage_mv":9000,"operating_current_ma":2000,"#,
+            r#""max_current_ma":3000,"capability_mismatch":false}}"#,
+        ));
+    }
+}